@@ -0,0 +1,99 @@
+//! Compares the price ladders on a dense tick market: every tick near the
+//! spread holds orders, and most of the traffic adds and cancels there with
+//! the odd market order sweeping a few levels. Run with
+//! `cargo bench --bench ladder`.
+
+use std::collections::BTreeMap;
+use std::hint::black_box;
+use std::time::{Duration, Instant};
+
+use alloy::primitives::U256;
+use clobex_engine::{Order, OrderBook, OrderId, Price, PriceLadder, Quantity, Side, SortedLadder};
+
+const MID: u64 = 10_000;
+/// Ticks seeded on each side of the spread before the run.
+const DEPTH: u64 = 500;
+/// Ticks from the spread new orders land within.
+const ACTIVE: u64 = 50;
+const OPERATIONS: usize = 200_000;
+
+/// Xorshift, so both ladders see the same operations.
+struct Rng(u64);
+
+impl Rng {
+    fn below(&mut self, bound: u64) -> u64 {
+        self.0 ^= self.0 << 13;
+        self.0 ^= self.0 >> 7;
+        self.0 ^= self.0 << 17;
+        self.0 % bound
+    }
+}
+
+fn limit(side: Side, quantity: u64, price: u64) -> Order {
+    Order::builder("bench", side, Quantity(U256::from(quantity)))
+        .limit(Price(U256::from(price)))
+        .build()
+        .unwrap()
+}
+
+fn market(side: Side, quantity: u64) -> Order {
+    Order::builder("bench", side, Quantity(U256::from(quantity)))
+        .build()
+        .unwrap()
+}
+
+/// Runs the workload against a book keeping its levels in `L` and returns
+/// the time taken and the number of orders left resting.
+fn run<L: PriceLadder>() -> (Duration, usize) {
+    let mut book = OrderBook::<L>::with_ladder(Price(U256::from(MID)));
+    let mut live: Vec<OrderId> = Vec::new();
+    for tick in 1..=DEPTH {
+        for _ in 0..4 {
+            let (bid, _) = book.add_order(limit(Side::Bid, 2, MID - tick), 0).unwrap();
+            let (ask, _) = book.add_order(limit(Side::Ask, 2, MID + tick), 0).unwrap();
+            live.extend([bid, ask]);
+        }
+    }
+
+    let mut rng = Rng(0x9e37_79b9_7f4a_7c15);
+    let started = Instant::now();
+    for _ in 0..OPERATIONS {
+        let side = match rng.below(2) {
+            0 => Side::Bid,
+            _ => Side::Ask,
+        };
+        match rng.below(20) {
+            0..=8 => {
+                let tick = 1 + rng.below(ACTIVE);
+                let price = match side {
+                    Side::Bid => MID - tick,
+                    Side::Ask => MID + tick,
+                };
+                let quantity = 1 + rng.below(4);
+                if let Ok((order_id, _)) = book.add_order(limit(side, quantity, price), 0) {
+                    live.push(order_id);
+                }
+            }
+            9..=16 if !live.is_empty() => {
+                let order_id = live.swap_remove(rng.below(live.len() as u64) as usize);
+                let _ = book.cancel(order_id);
+            }
+            _ => {
+                let quantity = 1 + rng.below(16);
+                black_box(book.add_order(market(side, quantity), 0)).ok();
+            }
+        }
+    }
+    let elapsed = started.elapsed();
+    (elapsed, book.footprint().resting_orders)
+}
+
+fn main() {
+    let (tree, tree_resting) = run::<BTreeMap<_, _>>();
+    let (sorted, sorted_resting) = run::<SortedLadder>();
+    assert_eq!(tree_resting, sorted_resting, "ladders diverged");
+    for (name, elapsed) in [("BTreeMap", tree), ("SortedLadder", sorted)] {
+        let per_operation = elapsed.as_nanos() / OPERATIONS as u128;
+        println!("{name:>12}: {elapsed:>10.2?} total, {per_operation} ns/op");
+    }
+}
@@ -0,0 +1,1778 @@
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::fmt;
+use std::ops::{Bound, RangeBounds};
+
+use alloy::primitives::{keccak256, B256, U256};
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::event::{CancelReason, Event};
+use crate::ladder::PriceLadder;
+use crate::matching::{Execution, MatchingStats, Taker};
+use crate::order::{Order, OrderId, OrderType, Side};
+use crate::units::{Price, Quantity};
+
+/// Orders queued at one price, keyed by arrival slot so iteration runs
+/// oldest first.
+pub(crate) type PriceLevel = BTreeMap<u64, Order>;
+
+/// Memory held by the order queues of a book.
+#[derive(Clone, Copy, Debug, Default)]
+pub struct BookFootprint {
+    pub price_levels: usize,
+    pub resting_orders: usize,
+    /// Entries allocated by the order index, including unused capacity.
+    pub allocated_slots: usize,
+    /// Approximate bytes held by price levels, queued orders and the order
+    /// index, excluding heap data owned by individual orders.
+    pub approximate_bytes: usize,
+}
+
+impl BookFootprint {
+    fn add_queue(&mut self, queue: &PriceLevel) {
+        self.resting_orders += queue.len();
+        self.approximate_bytes += queue.len() * size_of::<(u64, Order)>();
+    }
+
+    fn add_levels(&mut self, levels: &impl PriceLadder) {
+        self.price_levels += levels.len();
+        self.approximate_bytes += levels.len() * size_of::<(Price, PriceLevel)>();
+        for queue in levels.values() {
+            self.add_queue(queue);
+        }
+    }
+}
+
+/// Resting state of a book, for checkpointing and restarting it with
+/// `OrderBook::restore`. Orders are kept with their arrival slot, so time
+/// priority survives the round trip. Configuration set with the `with_*`
+/// methods, pending cancellation and expiry reports and the event log are
+/// not included.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct BookSnapshot {
+    pub last_price_level: Price,
+    pub next_order_id: u64,
+    pub next_slot: u64,
+    pub limit_orders: Vec<(u64, Order)>,
+    pub stop_orders: Vec<(u64, Order)>,
+    pub market_orders: Vec<(u64, Order)>,
+    /// One-cancels-other pairs, each listed once.
+    pub links: Vec<(OrderId, OrderId)>,
+    pub stats: MatchingStats,
+    /// Sequence number of the next event, which the restored log starts
+    /// from.
+    pub next_event_sequence: u64,
+}
+
+/// Why `OrderBook::restore` refused a snapshot. Snapshots are read back
+/// from storage, so a corrupted one must be rejected rather than rested:
+/// an over-filled order would wrap its remaining quantity.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum OrderError {
+    /// The order fails `Order::validate`, with the reason it gave.
+    Invalid(OrderId, String),
+    /// The order's type doesn't belong in the queue it was listed under.
+    WrongQueue(OrderId),
+    DuplicateOrder(OrderId),
+    DuplicateSlot(u64),
+    /// The id is not below the snapshot's `next_order_id`.
+    OrderIdOutOfRange(OrderId),
+    /// The slot is not below the snapshot's `next_slot`.
+    SlotOutOfRange(u64),
+    /// A one-cancels-other link names an order that isn't in the snapshot,
+    /// or links an order to itself.
+    DanglingLink(OrderId),
+}
+
+impl fmt::Display for OrderError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Invalid(order_id, reason) => {
+                write!(f, "Order {} is invalid: {reason}", order_id.0)
+            }
+            Self::WrongQueue(order_id) => write!(f, "Order {} is in the wrong queue", order_id.0),
+            Self::DuplicateOrder(order_id) => write!(f, "Order {} is listed twice", order_id.0),
+            Self::DuplicateSlot(slot) => write!(f, "Slot {slot} is used twice"),
+            Self::OrderIdOutOfRange(order_id) => {
+                write!(f, "Order {} is not below the next order id", order_id.0)
+            }
+            Self::SlotOutOfRange(slot) => write!(f, "Slot {slot} is not below the next slot"),
+            Self::DanglingLink(order_id) => {
+                write!(f, "Order {} is linked but not in the snapshot", order_id.0)
+            }
+        }
+    }
+}
+
+impl std::error::Error for OrderError {}
+
+/// Resting limit quantity at one price.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DepthLevel {
+    pub price: Price,
+    /// Visible remaining quantity; iceberg reserves are left out.
+    pub quantity: Quantity,
+    pub order_count: usize,
+}
+
+impl DepthLevel {
+    fn aggregate(price: Price, orders: &PriceLevel) -> Self {
+        Self {
+            price,
+            quantity: orders.values().fold(Quantity::ZERO, |total, order| {
+                total.saturating_add(order.visible_quantity())
+            }),
+            order_count: orders.len(),
+        }
+    }
+}
+
+/// Aggregated top of the book, best price first on both sides.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DepthSnapshot {
+    pub bids: Vec<DepthLevel>,
+    pub asks: Vec<DepthLevel>,
+}
+
+/// Caps how much of the resting depth near the top of a side a single
+/// owner may provide.
+#[derive(Clone, Copy, Debug)]
+pub struct ConcentrationLimit {
+    /// Number of best price levels per side the limit covers.
+    pub levels: usize,
+    /// Largest share of that depth one owner may hold, in basis points.
+    pub max_share_bps: u16,
+    /// Depth below which the limit isn't enforced, so a thin or empty
+    /// book can still be seeded.
+    pub min_depth: Quantity,
+}
+
+/// Lets a cancel-replace that improves its price by at most one tick keep
+/// part of its time priority instead of joining the back of the new level.
+///
+/// Priority is measured in arrival slots. A retained order is placed at
+/// `old_slot + (next_slot - old_slot) * (1 - retained_bps / 10_000)`,
+/// rounded down, so orders at the new level that arrived before that point
+/// stay ahead of it and later ones fall behind. If the slot is taken, the
+/// next free one is used; if none is left before the next arrival, the
+/// order goes to the back as usual. Replacements that cross the spread or
+/// raise the quantity never retain priority.
+#[derive(Clone, Copy, Debug)]
+pub struct PriorityRetention {
+    pub tick_size: Price,
+    /// Share of the order's queue age it keeps, in basis points.
+    pub retained_bps: u16,
+}
+
+/// Selects the resting limit orders of one owner for
+/// `OrderBook::cancel_all`. Price bounds are inclusive; unset fields match
+/// everything.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct CancelFilter {
+    pub owner: String,
+    pub side: Option<Side>,
+    pub min_price: Option<Price>,
+    pub max_price: Option<Price>,
+}
+
+impl CancelFilter {
+    pub fn owner(owner: impl Into<String>) -> Self {
+        Self {
+            owner: owner.into(),
+            side: None,
+            min_price: None,
+            max_price: None,
+        }
+    }
+
+    pub fn with_side(mut self, side: Side) -> Self {
+        self.side = Some(side);
+        self
+    }
+
+    pub fn with_min_price(mut self, min_price: Price) -> Self {
+        self.min_price = Some(min_price);
+        self
+    }
+
+    pub fn with_max_price(mut self, max_price: Price) -> Self {
+        self.max_price = Some(max_price);
+        self
+    }
+}
+
+/// Queue an order rests in while it waits to trade.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub(crate) enum QueueKind {
+    Limit,
+    Stop,
+    Market,
+}
+
+/// Where a resting order sits, so it can be reached without walking the
+/// queues. `price` is the limit price for limit orders and the stop price
+/// for stop orders; market queues have a single level.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct OrderLocation {
+    pub(crate) queue: QueueKind,
+    pub(crate) side: Side,
+    pub(crate) price: Price,
+    pub(crate) slot: u64,
+}
+
+/// Moves every order expired at `now` out of `levels` into `expired`,
+/// dropping levels left empty.
+fn expire_levels(levels: &mut impl PriceLadder, now: u64, expired: &mut Vec<Order>) {
+    for queue in levels.values_mut() {
+        expire_queue(queue, now, expired);
+    }
+    levels.retain(|_, queue| !queue.is_empty());
+}
+
+fn expire_queue(queue: &mut PriceLevel, now: u64, expired: &mut Vec<Order>) {
+    expired.extend(
+        queue
+            .extract_if(.., |_, order| order.is_expired(now))
+            .map(|(_, order)| order),
+    );
+}
+
+/// Removes the order at `location` from `levels`, dropping its level if it
+/// was the last one there.
+fn take_from(levels: &mut impl PriceLadder, location: OrderLocation) -> Order {
+    let level = levels.get_mut(&location.price).unwrap();
+    let order = level.remove(&location.slot).unwrap();
+    if level.is_empty() {
+        levels.remove(&location.price);
+    }
+    order
+}
+
+/// A limit order book for one market. Limit and stop price levels are
+/// kept in `L`; see `SortedLadder` for an alternative to the default tree.
+pub struct OrderBook<L: PriceLadder = BTreeMap<Price, PriceLevel>> {
+    pub(crate) bids: L,
+    pub(crate) asks: L,
+    pub(crate) stop_bids: L,
+    pub(crate) stop_asks: L,
+    pub(crate) market_bids: PriceLevel,
+    pub(crate) market_asks: PriceLevel,
+    /// Location of every order resting in one of the queues above.
+    pub(crate) index: HashMap<OrderId, OrderLocation>,
+    pub(crate) next_order_id: u64,
+    pub(crate) next_slot: u64,
+    pub(crate) last_price_level: Price,
+    pub(crate) stats: MatchingStats,
+    /// Market orders waiting for liquidity per side before new ones are
+    /// rejected. Unbounded when `None`.
+    pub(crate) max_market_queue_depth: Option<usize>,
+    /// Orders found expired while matching, held until the next `expire`.
+    pub(crate) expired_orders: Vec<Order>,
+    pub(crate) concentration_limit: Option<ConcentrationLimit>,
+    pub(crate) priority_retention: Option<PriorityRetention>,
+    /// One-cancels-other partners, stored in both directions.
+    pub(crate) links: HashMap<OrderId, OrderId>,
+    /// Orders cancelled because their one-cancels-other partner traded or
+    /// triggered, with the partner's id, held until
+    /// `take_linked_cancellations`.
+    pub(crate) linked_cancellations: Vec<(OrderId, Order)>,
+    /// Orders cancelled by self-trade prevention, held until
+    /// `take_self_trade_cancellations`.
+    pub(crate) self_trade_cancellations: Vec<Order>,
+    /// Furthest a market order may execute from the last traded price, in
+    /// basis points. Unbounded when `None`.
+    pub(crate) price_band_bps: Option<u16>,
+    /// Market orders cancelled for reaching the price protection, held
+    /// until `take_price_band_cancellations`.
+    pub(crate) price_band_cancellations: Vec<Order>,
+    /// Ids of each owner's orders in every queue, for `cancel_all` and
+    /// per-owner risk checks.
+    pub(crate) owner_orders: HashMap<String, HashSet<OrderId>>,
+    /// State changes not yet truncated, oldest first. See `events_since`.
+    pub(crate) events: Vec<Event>,
+    /// Sequence number of the first event in `events`.
+    pub(crate) first_event: u64,
+    /// Identifies this run of the book to feed consumers. See `with_epoch`.
+    pub(crate) epoch: u64,
+}
+
+impl OrderBook {
+    pub fn from_initial_price(initial_price: Price) -> Self {
+        Self::with_ladder(initial_price)
+    }
+}
+
+impl<L: PriceLadder> OrderBook<L> {
+    /// Like `from_initial_price`, with price levels kept in `L`.
+    pub fn with_ladder(initial_price: Price) -> Self {
+        Self {
+            bids: L::default(),
+            asks: L::default(),
+            stop_bids: L::default(),
+            stop_asks: L::default(),
+            market_bids: PriceLevel::new(),
+            market_asks: PriceLevel::new(),
+            index: HashMap::new(),
+            next_order_id: 1,
+            next_slot: 0,
+            last_price_level: initial_price,
+            stats: MatchingStats::default(),
+            max_market_queue_depth: None,
+            expired_orders: Vec::new(),
+            concentration_limit: None,
+            priority_retention: None,
+            links: HashMap::new(),
+            linked_cancellations: Vec::new(),
+            self_trade_cancellations: Vec::new(),
+            price_band_bps: None,
+            price_band_cancellations: Vec::new(),
+            owner_orders: HashMap::new(),
+            events: Vec::new(),
+            first_event: 0,
+            epoch: 0,
+        }
+    }
+
+    pub fn with_max_market_queue_depth(mut self, max_market_queue_depth: usize) -> Self {
+        self.max_market_queue_depth = Some(max_market_queue_depth);
+        self
+    }
+
+    pub fn with_concentration_limit(mut self, concentration_limit: ConcentrationLimit) -> Self {
+        self.concentration_limit = Some(concentration_limit);
+        self
+    }
+
+    pub fn with_priority_retention(mut self, priority_retention: PriorityRetention) -> Self {
+        self.priority_retention = Some(priority_retention);
+        self
+    }
+
+    /// Stops market orders from executing more than `max_deviation_bps`
+    /// away from the last traded price.
+    pub fn with_price_band(mut self, max_deviation_bps: u16) -> Self {
+        self.price_band_bps = Some(max_deviation_bps);
+        self
+    }
+
+    /// Stamps everything the feeds publish from this book with `epoch`.
+    /// Feed sequence numbers start over when a book is restored after a
+    /// restart, so give each run a new, larger epoch, such as the unix
+    /// timestamp it started at, and clients that see it change know to
+    /// drop cached deltas and resnapshot.
+    pub fn with_epoch(mut self, epoch: u64) -> Self {
+        self.epoch = epoch;
+        self
+    }
+
+    pub fn epoch(&self) -> u64 {
+        self.epoch
+    }
+
+    pub fn last_price_level(&self) -> Price {
+        self.last_price_level
+    }
+
+    pub fn stats(&self) -> &MatchingStats {
+        &self.stats
+    }
+
+    /// The events still held in the book's log, oldest first. See `Event`.
+    pub fn events(&self) -> &[Event] {
+        &self.events
+    }
+
+    /// Sequence number the next logged event will get. Every event is
+    /// numbered from the book's creation, and the numbering carries over
+    /// snapshots, so a consumer that keeps the number it has read up to
+    /// stays in step across truncation and restores.
+    pub fn next_event_sequence(&self) -> u64 {
+        self.first_event + self.events.len() as u64
+    }
+
+    /// Sequence number of the oldest event still held.
+    pub fn first_event_sequence(&self) -> u64 {
+        self.first_event
+    }
+
+    /// The events numbered `sequence` and up. Fails if some of them have
+    /// been truncated: a consumer reading from there has missed events and
+    /// must start again from a snapshot.
+    pub fn events_since(&self, sequence: u64) -> Result<&[Event]> {
+        if sequence < self.first_event {
+            bail!(
+                "Events from {sequence} were truncated; the oldest held is {}",
+                self.first_event
+            );
+        }
+        let start = sequence - self.first_event;
+        Ok(&self.events[(start as usize).min(self.events.len())..])
+    }
+
+    /// Drops the events numbered below `sequence`, once every consumer has
+    /// read past them. Keeps the log from growing without bound.
+    pub fn truncate_events_before(&mut self, sequence: u64) {
+        let count = sequence.saturating_sub(self.first_event) as usize;
+        let count = count.min(self.events.len());
+        self.events.drain(..count);
+        self.first_event += count as u64;
+    }
+
+    pub fn footprint(&self) -> BookFootprint {
+        let mut footprint = BookFootprint::default();
+        for levels in [&self.bids, &self.asks, &self.stop_bids, &self.stop_asks] {
+            footprint.add_levels(levels);
+        }
+        footprint.add_queue(&self.market_bids);
+        footprint.add_queue(&self.market_asks);
+        footprint.allocated_slots = self.index.capacity();
+        footprint.approximate_bytes +=
+            self.index.capacity() * size_of::<(OrderId, OrderLocation)>();
+        footprint
+    }
+
+    /// Aggregates the best `levels` limit price levels on each side.
+    pub fn depth(&self, levels: usize) -> DepthSnapshot {
+        let aggregate =
+            |(price, orders): (&Price, &PriceLevel)| DepthLevel::aggregate(*price, orders);
+        DepthSnapshot {
+            bids: self.bids.iter().rev().take(levels).map(aggregate).collect(),
+            asks: self.asks.iter().take(levels).map(aggregate).collect(),
+        }
+    }
+
+    /// Aggregates the limit price level at `price` on `side`, if any
+    /// orders rest there.
+    pub fn depth_at(&self, side: Side, price: Price) -> Option<DepthLevel> {
+        let levels = match side {
+            Side::Bid => &self.bids,
+            Side::Ask => &self.asks,
+        };
+        let orders = levels.get(&price)?;
+        Some(DepthLevel::aggregate(price, orders))
+    }
+
+    /// Captures the queues, links, counters and last traded price.
+    pub fn snapshot(&self) -> BookSnapshot {
+        let slotted = |queue: &PriceLevel| {
+            let orders = queue.iter().map(|(slot, order)| (*slot, order.clone()));
+            orders.collect::<Vec<_>>()
+        };
+        let flatten = |levels: [&L; 2]| {
+            let queues = levels.into_iter().flat_map(|levels| levels.values());
+            queues.flat_map(slotted).collect()
+        };
+        let mut market_orders = slotted(&self.market_bids);
+        market_orders.extend(slotted(&self.market_asks));
+        let mut links: Vec<_> = self
+            .links
+            .iter()
+            .filter(|(first, second)| first < second)
+            .map(|(first, second)| (*first, *second))
+            .collect();
+        links.sort();
+        BookSnapshot {
+            last_price_level: self.last_price_level,
+            next_order_id: self.next_order_id,
+            next_slot: self.next_slot,
+            limit_orders: flatten([&self.bids, &self.asks]),
+            stop_orders: flatten([&self.stop_bids, &self.stop_asks]),
+            market_orders,
+            links,
+            stats: self.stats.clone(),
+            next_event_sequence: self.next_event_sequence(),
+        }
+    }
+
+    /// Keccak-256 hash of the book's snapshot. Replicas that applied the
+    /// same commands have the same hash.
+    pub fn state_hash(&self) -> B256 {
+        let snapshot = serde_json::to_vec(&self.snapshot()).expect("snapshots serialize");
+        keccak256(snapshot)
+    }
+
+    /// Rebuilds a book from `snapshot`, with default configuration and an
+    /// empty event log that numbers on from the snapshot's. Every order is
+    /// checked as `add_order` would check it and must sit in the queue its
+    /// type belongs to, ids and slots must be unique and below the
+    /// snapshot's counters, and links must join orders that are present.
+    pub fn restore(snapshot: BookSnapshot) -> Result<Self, OrderError> {
+        let mut book = Self::with_ladder(snapshot.last_price_level);
+        book.next_order_id = snapshot.next_order_id;
+        book.next_slot = snapshot.next_slot;
+        book.stats = snapshot.stats;
+        book.first_event = snapshot.next_event_sequence;
+        let mut slots = HashSet::new();
+        for (queue, orders) in [
+            (QueueKind::Limit, snapshot.limit_orders),
+            (QueueKind::Stop, snapshot.stop_orders),
+            (QueueKind::Market, snapshot.market_orders),
+        ] {
+            for (slot, order) in orders {
+                let order_type = order
+                    .validate()
+                    .map_err(|err| OrderError::Invalid(order.id, err.to_string()))?;
+                let in_its_queue = match queue {
+                    QueueKind::Limit => order_type == OrderType::Limit,
+                    QueueKind::Stop => matches!(order_type, OrderType::Stop | OrderType::StopLimit),
+                    QueueKind::Market => order_type == OrderType::Market,
+                };
+                if !in_its_queue {
+                    return Err(OrderError::WrongQueue(order.id));
+                }
+                if order.id.0 >= book.next_order_id {
+                    return Err(OrderError::OrderIdOutOfRange(order.id));
+                }
+                if slot >= book.next_slot {
+                    return Err(OrderError::SlotOutOfRange(slot));
+                }
+                if book.index.contains_key(&order.id) {
+                    return Err(OrderError::DuplicateOrder(order.id));
+                }
+                if !slots.insert(slot) {
+                    return Err(OrderError::DuplicateSlot(slot));
+                }
+                book.rest_at(queue, slot, order);
+            }
+        }
+        for (first, second) in snapshot.links {
+            for order_id in [first, second] {
+                let linked =
+                    book.index.contains_key(&order_id) && !book.links.contains_key(&order_id);
+                if !linked || first == second {
+                    return Err(OrderError::DanglingLink(order_id));
+                }
+            }
+            book.links.insert(first, second);
+            book.links.insert(second, first);
+        }
+        Ok(book)
+    }
+
+    /// Releases memory retained after large sweeps. The order index, and a
+    /// ladder that keeps its levels in one vector, keep their peak capacity
+    /// otherwise, so callers should run this periodically
+    /// (e.g. when `footprint().allocated_slots` far exceeds
+    /// `resting_orders`). Returns the footprint after compaction.
+    pub fn compact(&mut self) -> BookFootprint {
+        self.index.shrink_to_fit();
+        for levels in [
+            &mut self.bids,
+            &mut self.asks,
+            &mut self.stop_bids,
+            &mut self.stop_asks,
+        ] {
+            levels.shrink_to_fit();
+        }
+        self.footprint()
+    }
+
+    /// Returns the resting order with `order_id`, if it is still on the
+    /// book.
+    pub fn order(&self, order_id: OrderId) -> Option<&Order> {
+        let location = self.index.get(&order_id)?;
+        let queue = match (location.queue, location.side) {
+            (QueueKind::Market, Side::Bid) => &self.market_bids,
+            (QueueKind::Market, Side::Ask) => &self.market_asks,
+            (QueueKind::Limit, Side::Bid) => self.bids.get(&location.price)?,
+            (QueueKind::Limit, Side::Ask) => self.asks.get(&location.price)?,
+            (QueueKind::Stop, Side::Bid) => self.stop_bids.get(&location.price)?,
+            (QueueKind::Stop, Side::Ask) => self.stop_asks.get(&location.price)?,
+        };
+        queue.get(&location.slot)
+    }
+
+    /// Removes the resting order with `order_id` from whichever queue holds
+    /// it and returns it. Takes a lookup in the order index and one in the
+    /// price level, without walking any queue.
+    pub fn cancel(&mut self, order_id: OrderId) -> Result<Order> {
+        let Some(location) = self.index.remove(&order_id) else {
+            bail!("Order not found");
+        };
+        self.unlink(order_id);
+        self.cancelled(order_id, CancelReason::Requested);
+        Ok(self.take(location))
+    }
+
+    /// Removes every resting limit order `filter` selects and returns them,
+    /// best price first on each side, bids before asks. Only the owner's
+    /// own orders are looked at, however many others the book holds.
+    pub fn cancel_all(&mut self, filter: &CancelFilter) -> Vec<Order> {
+        let prices = (
+            filter.min_price.map_or(Bound::Unbounded, Bound::Included),
+            filter.max_price.map_or(Bound::Unbounded, Bound::Included),
+        );
+        let owned = self.owner_orders.get(&filter.owner).into_iter().flatten();
+        let mut selected: Vec<(OrderId, OrderLocation)> = owned
+            .map(|order_id| (*order_id, self.index[order_id]))
+            .filter(|(_, location)| {
+                location.queue == QueueKind::Limit
+                    && filter.side.is_none_or(|side| side == location.side)
+                    && prices.contains(&location.price)
+            })
+            .collect();
+        selected.sort_by(|(_, a), (_, b)| {
+            let by_price = match a.side {
+                Side::Bid => b.price.cmp(&a.price),
+                Side::Ask => a.price.cmp(&b.price),
+            };
+            (a.side == Side::Ask)
+                .cmp(&(b.side == Side::Ask))
+                .then(by_price)
+                .then(a.slot.cmp(&b.slot))
+        });
+        let mut cancelled = Vec::new();
+        for (order_id, location) in selected {
+            self.index.remove(&order_id);
+            self.unlink(order_id);
+            self.cancelled(order_id, CancelReason::Requested);
+            cancelled.push(self.take(location));
+        }
+        cancelled
+    }
+
+    /// Returns the orders cancelled since the last call because their
+    /// one-cancels-other partner traded or triggered, each paired with the
+    /// partner's id.
+    pub fn take_linked_cancellations(&mut self) -> Vec<(OrderId, Order)> {
+        std::mem::take(&mut self.linked_cancellations)
+    }
+
+    /// Returns the orders cancelled by self-trade prevention since the last
+    /// call: resting orders as they were when cancelled, and incoming
+    /// orders with the fills and decrements applied before their remainder
+    /// was cancelled.
+    pub fn take_self_trade_cancellations(&mut self) -> Vec<Order> {
+        std::mem::take(&mut self.self_trade_cancellations)
+    }
+
+    /// Returns the queued market orders whose remainder was cancelled since
+    /// the last call because the rest of the opposite side lay outside the
+    /// price band or the order's slippage limit.
+    pub fn take_price_band_cancellations(&mut self) -> Vec<Order> {
+        std::mem::take(&mut self.price_band_cancellations)
+    }
+
+    /// Changes the price and total quantity of a resting limit order at
+    /// `now`. Lowering the quantity at the same price amends the order in
+    /// place and keeps its time priority. Any other change is a
+    /// cancel-replace: the order keeps its id but moves to the back of the
+    /// queue at its new price, and is matched first if that price crosses
+    /// the spread, in which case the execution is returned. Small price
+    /// improvements may keep part of their priority under the book's
+    /// `PriorityRetention`.
+    pub fn amend(
+        &mut self,
+        order_id: OrderId,
+        new_price: Price,
+        new_quantity: Quantity,
+        now: u64,
+    ) -> Result<Option<Execution>> {
+        let Some(&location) = self.index.get(&order_id) else {
+            bail!("Order not found");
+        };
+        if location.queue != QueueKind::Limit {
+            bail!("Only resting limit orders can be amended");
+        }
+        let current = self.order(order_id).unwrap();
+        if current.is_expired(now) {
+            bail!("Order has expired");
+        }
+        let mut amended = current.clone();
+        amended.limit_price = Some(new_price);
+        amended.quantity = new_quantity;
+        if amended.validate()? != OrderType::Limit {
+            bail!("Invalid order type");
+        }
+        if amended.post_only && self.crosses_spread(amended.side, new_price) {
+            bail!("Post-only order would take liquidity");
+        }
+        if amended.limit_price == current.limit_price && new_quantity <= current.quantity {
+            *self.order_mut(location) = amended;
+            self.events.push(Event::Resized {
+                order_id,
+                quantity: new_quantity,
+            });
+            return Ok(None);
+        }
+
+        // the concentration check must not count the order being replaced
+        let current = self.take(location);
+        if let Err(err) = self.check_concentration(&amended, now) {
+            self.place(location, current);
+            return Err(err);
+        }
+        self.cancelled(order_id, CancelReason::Replaced);
+        if let Some(slot) = self.retained_slot(location.slot, &current, &amended, now) {
+            let location = OrderLocation {
+                price: new_price,
+                slot,
+                ..location
+            };
+            self.events.push(Event::Rested {
+                order: amended.clone(),
+                slot,
+            });
+            self.index.insert(order_id, location);
+            self.place(location, amended);
+            return Ok(None);
+        }
+        self.index.remove(&order_id);
+        Ok(self.insert_order(amended, OrderType::Limit, now))
+    }
+
+    /// Slot a replaced order keeps under the priority retention rule, if
+    /// it qualifies and a free slot is left.
+    fn retained_slot(
+        &self,
+        old_slot: u64,
+        current: &Order,
+        amended: &Order,
+        now: u64,
+    ) -> Option<u64> {
+        let retention = self.priority_retention?;
+        if amended.quantity > current.quantity {
+            return None;
+        }
+        let improvement = match amended.side {
+            Side::Bid => amended.price_bound().checked_sub(current.price_bound()),
+            Side::Ask => current.price_bound().checked_sub(amended.price_bound()),
+        }?;
+        if improvement == Price::ZERO || improvement > retention.tick_size {
+            return None;
+        }
+        let quantity = amended.quantity - amended.filled_quantity;
+        if !self.simulate(Taker::of(amended), quantity, now).is_empty() {
+            return None;
+        }
+
+        let age = u128::from(self.next_slot - old_slot);
+        let lost = age * u128::from(10_000 - retention.retained_bps.min(10_000)) / 10_000;
+        let mut slot = old_slot + lost as u64;
+        let level = match amended.side {
+            Side::Bid => self.bids.get(&amended.price_bound()),
+            Side::Ask => self.asks.get(&amended.price_bound()),
+        };
+        while level.is_some_and(|level| level.contains_key(&slot)) {
+            slot += 1;
+        }
+        (slot < self.next_slot).then_some(slot)
+    }
+
+    /// Removes every good-til-date order that has expired at `now` from the
+    /// limit, stop and market queues. Returns them together with any orders
+    /// the matcher already dropped for being expired since the last call,
+    /// so callers see each expiration exactly once.
+    pub fn expire(&mut self, now: u64) -> Vec<Order> {
+        let mut expired = std::mem::take(&mut self.expired_orders);
+        let already_logged = expired.len();
+        for levels in [
+            &mut self.bids,
+            &mut self.asks,
+            &mut self.stop_bids,
+            &mut self.stop_asks,
+        ] {
+            expire_levels(levels, now, &mut expired);
+        }
+        expire_queue(&mut self.market_bids, now, &mut expired);
+        expire_queue(&mut self.market_asks, now, &mut expired);
+        self.unindex(&expired);
+        for order in &expired {
+            self.unlink(order.id);
+        }
+        let newly_expired = expired[already_logged..].iter();
+        self.events
+            .extend(newly_expired.map(|order| Event::Expired(order.id)));
+        expired
+    }
+
+    /// Adds `order` to the book under a newly assigned id, which is returned
+    /// with the trade if there was one. A limit order that crosses the
+    /// spread is matched immediately up to its limit price and only the
+    /// remainder rests; the executed taker and its makers are returned in
+    /// that case. Market orders are queued for
+    /// `take_bid_order`/`take_ask_order`, and stop orders wait for
+    /// `trigger_stops`.
+    ///
+    /// Immediate-or-cancel and fill-or-kill orders, market or limit, are
+    /// matched on arrival and never rest or queue: whatever doesn't execute
+    /// is cancelled, and a fill-or-kill order that can't execute in full
+    /// doesn't trade at all.
+    pub fn add_order(&mut self, order: Order, now: u64) -> Result<(OrderId, Option<Execution>)> {
+        let (order, order_type) = self.accept(order, now)?;
+        Ok((order.id, self.insert_order(order, order_type, now)))
+    }
+
+    /// Adds two orders that cancel each other: as soon as either trades or
+    /// triggers, the other is cancelled and reported by
+    /// `take_linked_cancellations`. Both are checked before either is
+    /// placed, then `first` goes in before `second`; if `first` trades on
+    /// arrival, `second` is never placed. Typically a take-profit limit
+    /// order paired with a stop-loss.
+    pub fn add_oco(
+        &mut self,
+        first: Order,
+        second: Order,
+        now: u64,
+    ) -> Result<((OrderId, OrderId), Vec<Execution>)> {
+        if first.time_in_force.is_immediate() || second.time_in_force.is_immediate() {
+            bail!("One-cancels-other legs must be resting orders");
+        }
+        let (first, first_type) = self.accept(first, now)?;
+        let (second, second_type) = self.accept(second, now)?;
+        let ids = (first.id, second.id);
+        self.links.insert(ids.0, ids.1);
+        self.links.insert(ids.1, ids.0);
+        self.events.push(Event::Linked(ids.0, ids.1));
+
+        let mut executions = Vec::from_iter(self.insert_order(first, first_type, now));
+        if self.links.contains_key(&ids.1) {
+            executions.extend(self.insert_order(second, second_type, now));
+        } else {
+            self.cancelled(ids.1, CancelReason::Linked);
+            self.linked_cancellations.push((ids.0, second));
+        }
+        Ok((ids, executions))
+    }
+
+    /// Runs the checks a new order must pass and assigns its id.
+    fn accept(&mut self, mut order: Order, now: u64) -> Result<(Order, OrderType)> {
+        let order_type = order.validate()?;
+        if order.is_expired(now) {
+            bail!("Order has expired");
+        }
+        if order_type == OrderType::Market && !order.time_in_force.is_immediate() {
+            let queue_depth = match order.side {
+                Side::Bid => self.market_bids.len(),
+                Side::Ask => self.market_asks.len(),
+            };
+            if self
+                .max_market_queue_depth
+                .is_some_and(|max_depth| queue_depth >= max_depth)
+            {
+                bail!("Market order queue is full");
+            }
+        }
+        if order.post_only && self.crosses_spread(order.side, order.price_bound()) {
+            bail!("Post-only order would take liquidity");
+        }
+        if order_type == OrderType::Limit && !order.time_in_force.is_immediate() {
+            self.check_concentration(&order, now)?;
+        }
+        order.id = OrderId(self.next_order_id);
+        self.next_order_id += 1;
+        self.events.push(Event::Accepted(order.clone()));
+        Ok((order, order_type))
+    }
+
+    /// Rejects a limit order whose resting remainder would leave its owner
+    /// above the configured share of depth in the best levels of its side.
+    fn check_concentration(&self, order: &Order, now: u64) -> Result<()> {
+        let Some(limit) = self.concentration_limit else {
+            return Ok(());
+        };
+        let quantity = order.quantity - order.filled_quantity;
+        let fills = self.simulate(Taker::of(order), quantity, now);
+        let resting = quantity - fills.iter().map(|fill| fill.quantity).sum::<Quantity>();
+        if resting == Quantity::ZERO {
+            return Ok(());
+        }
+
+        let levels = match order.side {
+            Side::Bid => &self.bids,
+            Side::Ask => &self.asks,
+        };
+        let price = order.price_bound();
+        let better = match order.side {
+            Side::Bid => levels.range(price..).count(),
+            Side::Ask => levels.range(..=price).count(),
+        };
+        let own_level = usize::from(!levels.contains_key(&price));
+        if better + own_level > limit.levels {
+            return Ok(());
+        }
+        let top: Box<dyn Iterator<Item = &PriceLevel>> = match order.side {
+            Side::Bid => Box::new(levels.values().rev().take(limit.levels - own_level)),
+            Side::Ask => Box::new(levels.values().take(limit.levels - own_level)),
+        };
+
+        let mut total_depth = resting;
+        let mut owner_depth = resting;
+        for maker in top
+            .flat_map(PriceLevel::values)
+            .filter(|maker| !maker.is_expired(now))
+        {
+            let available = maker.quantity - maker.filled_quantity;
+            total_depth = total_depth.saturating_add(available);
+            if maker.owner == order.owner {
+                owner_depth = owner_depth.saturating_add(available);
+            }
+        }
+        let max_share = U256::from(limit.max_share_bps);
+        if total_depth >= limit.min_depth
+            && owner_depth.0.saturating_mul(U256::from(10_000))
+                > total_depth.0.saturating_mul(max_share)
+        {
+            bail!("Owner would exceed the maker concentration limit");
+        }
+        Ok(())
+    }
+
+    /// Places an already validated order into the queue for its type.
+    pub(crate) fn insert_order(
+        &mut self,
+        order: Order,
+        order_type: OrderType,
+        now: u64,
+    ) -> Option<Execution> {
+        match order_type {
+            OrderType::Market if !order.time_in_force.is_immediate() => {
+                self.rest(QueueKind::Market, order)
+            }
+            OrderType::Market | OrderType::Limit => {
+                let (execution, cancelled) = self.match_incoming_order(order, now);
+                let mut order = execution.taker.clone();
+                let executed = (!execution.trades.is_empty()).then_some(execution);
+                if executed.is_some() {
+                    self.cancel_linked(order.id);
+                }
+                if cancelled {
+                    self.unlink(order.id);
+                    self.cancelled(order.id, CancelReason::SelfTrade);
+                    self.self_trade_cancellations.push(order);
+                    return executed;
+                }
+                if order.filled_quantity < order.quantity && !order.time_in_force.is_immediate() {
+                    order.rested_at = now;
+                    self.rest(QueueKind::Limit, order);
+                }
+                return executed;
+            }
+            OrderType::Stop | OrderType::StopLimit => self.rest(QueueKind::Stop, order),
+        }
+        None
+    }
+
+    /// Appends `order` to the back of its level in `queue` and records where
+    /// it went.
+    fn rest(&mut self, queue: QueueKind, order: Order) {
+        let slot = self.next_slot;
+        self.next_slot += 1;
+        self.events.push(Event::Rested {
+            order: order.clone(),
+            slot,
+        });
+        self.rest_at(queue, slot, order);
+    }
+
+    pub(crate) fn rest_at(&mut self, queue: QueueKind, slot: u64, order: Order) {
+        let price = match queue {
+            QueueKind::Stop => order.stop_price.expect("stop orders have a stop price"),
+            QueueKind::Limit | QueueKind::Market => order.price_bound(),
+        };
+        let location = OrderLocation {
+            queue,
+            side: order.side,
+            price,
+            slot,
+        };
+        self.index.insert(order.id, location);
+        self.place(location, order);
+    }
+
+    pub(crate) fn place(&mut self, location: OrderLocation, order: Order) {
+        let order_ids = self.owner_orders.entry(order.owner.clone()).or_default();
+        order_ids.insert(order.id);
+        let price = location.price;
+        let level = match (location.queue, location.side) {
+            (QueueKind::Market, Side::Bid) => &mut self.market_bids,
+            (QueueKind::Market, Side::Ask) => &mut self.market_asks,
+            (QueueKind::Limit, Side::Bid) => self.bids.level_mut(price),
+            (QueueKind::Limit, Side::Ask) => self.asks.level_mut(price),
+            (QueueKind::Stop, Side::Bid) => self.stop_bids.level_mut(price),
+            (QueueKind::Stop, Side::Ask) => self.stop_asks.level_mut(price),
+        };
+        level.insert(location.slot, order);
+    }
+
+    /// Removes the order at `location`, dropping its level if it was the
+    /// last one there. The index entry is left to the caller.
+    pub(crate) fn take(&mut self, location: OrderLocation) -> Order {
+        let order = match (location.queue, location.side) {
+            (QueueKind::Market, Side::Bid) => self.market_bids.remove(&location.slot).unwrap(),
+            (QueueKind::Market, Side::Ask) => self.market_asks.remove(&location.slot).unwrap(),
+            (QueueKind::Limit, Side::Bid) => take_from(&mut self.bids, location),
+            (QueueKind::Limit, Side::Ask) => take_from(&mut self.asks, location),
+            (QueueKind::Stop, Side::Bid) => take_from(&mut self.stop_bids, location),
+            (QueueKind::Stop, Side::Ask) => take_from(&mut self.stop_asks, location),
+        };
+        self.disown(&order);
+        order
+    }
+
+    pub(crate) fn order_mut(&mut self, location: OrderLocation) -> &mut Order {
+        let level = match (location.queue, location.side) {
+            (QueueKind::Market, Side::Bid) => &mut self.market_bids,
+            (QueueKind::Market, Side::Ask) => &mut self.market_asks,
+            (QueueKind::Limit, Side::Bid) => self.bids.get_mut(&location.price).unwrap(),
+            (QueueKind::Limit, Side::Ask) => self.asks.get_mut(&location.price).unwrap(),
+            (QueueKind::Stop, Side::Bid) => self.stop_bids.get_mut(&location.price).unwrap(),
+            (QueueKind::Stop, Side::Ask) => self.stop_asks.get_mut(&location.price).unwrap(),
+        };
+        level.get_mut(&location.slot).unwrap()
+    }
+
+    /// Cancels the one-cancels-other partner of `order_id` once it has
+    /// traded or triggered. Returns the partner's id if it isn't resting,
+    /// in which case the caller has to drop it.
+    pub(crate) fn cancel_linked(&mut self, order_id: OrderId) -> Option<OrderId> {
+        let partner = self.links.remove(&order_id)?;
+        self.links.remove(&partner);
+        let Some(location) = self.index.remove(&partner) else {
+            return Some(partner);
+        };
+        let cancelled = self.take(location);
+        self.cancelled(partner, CancelReason::Linked);
+        self.linked_cancellations.push((order_id, cancelled));
+        None
+    }
+
+    pub(crate) fn unlink(&mut self, order_id: OrderId) {
+        if let Some(partner) = self.links.remove(&order_id) {
+            self.links.remove(&partner);
+        }
+    }
+
+    pub(crate) fn cancelled(&mut self, order_id: OrderId, reason: CancelReason) {
+        self.events.push(Event::Cancelled { order_id, reason });
+    }
+
+    /// Forgets the locations of orders that have left the book.
+    pub(crate) fn unindex<'a>(&mut self, orders: impl IntoIterator<Item = &'a Order>) {
+        for order in orders {
+            self.index.remove(&order.id);
+            self.disown(order);
+        }
+    }
+
+    /// Drops `order` from its owner's orders, if it was resting.
+    fn disown(&mut self, order: &Order) {
+        let Some(order_ids) = self.owner_orders.get_mut(&order.owner) else {
+            return;
+        };
+        order_ids.remove(&order.id);
+        if order_ids.is_empty() {
+            self.owner_orders.remove(&order.owner);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::order::TimeInForce;
+    use crate::test_utils::*;
+
+    #[test]
+    fn rejects_zero_quantity() {
+        let mut book = OrderBook::from_initial_price(Price(U256::from(100)));
+        let err = book
+            .add_order(limit_ask(U256::ZERO, U256::from(100)), 0)
+            .unwrap_err();
+        assert_eq!(err.to_string(), "Order quantity is zero");
+        assert!(book.asks.is_empty());
+    }
+
+    #[test]
+    fn rejects_filled_quantity_above_quantity() {
+        let mut book = OrderBook::from_initial_price(Price(U256::from(100)));
+        let mut corrupted = limit_ask(U256::from(5), U256::from(100));
+        corrupted.filled_quantity = Quantity(U256::from(6));
+        let err = book.add_order(corrupted, 0).unwrap_err();
+        assert_eq!(err.to_string(), "Filled quantity exceeds order quantity");
+        assert!(book.asks.is_empty());
+    }
+
+    #[test]
+    fn rejects_already_filled_order() {
+        let mut book = OrderBook::from_initial_price(Price(U256::from(100)));
+        let mut filled = market_bid(U256::from(5));
+        filled.filled_quantity = Quantity(U256::from(5));
+        let err = book.add_order(filled, 0).unwrap_err();
+        assert_eq!(err.to_string(), "Order is already filled");
+        assert!(book.market_bids.is_empty());
+    }
+
+    #[test]
+    fn accepts_limit_prices_at_the_top_of_the_range() {
+        let mut book = OrderBook::from_initial_price(Price(U256::from(100)));
+        book.add_order(limit_ask(U256::from(1), U256::MAX), 0)
+            .unwrap();
+        assert!(book.asks.contains_key(&Price::MAX));
+
+        let Execution { taker, makers, .. } = book
+            .add_order(limit_bid(U256::from(1), U256::MAX), 0)
+            .unwrap()
+            .1
+            .unwrap();
+        assert_eq!(taker.filled_quantity, Quantity(U256::from(1)));
+        assert_eq!(makers.len(), 1);
+        assert!(book.asks.is_empty());
+        assert!(book.bids.is_empty());
+    }
+
+    #[test]
+    fn builder_types_orders_by_their_prices() {
+        let stop_limit = Order::builder("owner", Side::Bid, Quantity(U256::from(1)))
+            .limit(Price(U256::from(101)))
+            .stop(Price(U256::from(100)))
+            .build()
+            .unwrap();
+        assert_eq!(stop_limit.order_type, OrderType::StopLimit);
+
+        let err = Order::builder("owner", Side::Bid, Quantity(U256::from(1)))
+            .post_only()
+            .build()
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Post-only order must be a resting limit order"
+        );
+
+        let mut mistyped = limit_bid(U256::from(1), U256::from(100));
+        mistyped.order_type = OrderType::Market;
+        let mut book = OrderBook::from_initial_price(Price(U256::from(100)));
+        let err = book.add_order(mistyped, 0).unwrap_err();
+        assert_eq!(err.to_string(), "Order prices do not match its type");
+    }
+
+    #[test]
+    fn crossing_limit_order_rests_only_its_remainder() {
+        let mut book = OrderBook::from_initial_price(Price(U256::from(100)));
+        book.add_order(limit_ask(U256::from(2), U256::from(101)), 0)
+            .unwrap();
+        book.add_order(limit_ask(U256::from(2), U256::from(103)), 0)
+            .unwrap();
+
+        let Execution { taker, makers, .. } = book
+            .add_order(limit_bid(U256::from(5), U256::from(102)), 0)
+            .unwrap()
+            .1
+            .unwrap();
+        assert_eq!(taker.filled_quantity, Quantity(U256::from(2)));
+        assert_eq!(makers.len(), 1);
+        assert_eq!(
+            book.bids[&Price(U256::from(102))]
+                .first_key_value()
+                .unwrap()
+                .1
+                .filled_quantity,
+            Quantity(U256::from(2))
+        );
+        assert!(book.asks.contains_key(&Price(U256::from(103))));
+    }
+
+    #[test]
+    fn immediate_orders_never_rest() {
+        let mut book = OrderBook::from_initial_price(Price(U256::from(100)));
+        book.add_order(limit_ask(U256::from(2), U256::from(101)), 0)
+            .unwrap();
+
+        let mut fok = limit_bid(U256::from(3), U256::from(101));
+        fok.time_in_force = TimeInForce::FillOrKill;
+        assert!(book.add_order(fok, 0).unwrap().1.is_none());
+        assert_eq!(
+            book.asks[&Price(U256::from(101))]
+                .first_key_value()
+                .unwrap()
+                .1
+                .filled_quantity,
+            Quantity::ZERO
+        );
+        assert!(book.bids.is_empty());
+
+        let mut ioc = market_bid(U256::from(3));
+        ioc.time_in_force = TimeInForce::ImmediateOrCancel;
+        let taker = book.add_order(ioc, 0).unwrap().1.unwrap().taker;
+        assert_eq!(taker.filled_quantity, Quantity(U256::from(2)));
+        assert!(book.asks.is_empty());
+        assert!(book.market_bids.is_empty());
+    }
+
+    #[test]
+    fn expires_good_til_date_orders() {
+        let mut book = OrderBook::from_initial_price(Price(U256::from(100)));
+        let mut gtd = limit_ask(U256::from(1), U256::from(101));
+        gtd.time_in_force = TimeInForce::GoodTilDate;
+        gtd.expire_timestamp = 10;
+        book.add_order(gtd.clone(), 0).unwrap();
+        let err = book.add_order(gtd.clone(), 10).unwrap_err();
+        assert_eq!(err.to_string(), "Order has expired");
+
+        // not matched once past its deadline, even before `expire` runs
+        assert!(book
+            .add_order(market_bid(U256::from(1)), 10)
+            .unwrap()
+            .1
+            .is_none());
+        assert!(book.take_bid_order(0, 10).is_none());
+        assert!(book.asks.is_empty());
+
+        book.add_order(gtd, 5).unwrap();
+        let expired = book.expire(10);
+        assert_eq!(expired.len(), 2);
+        assert!(book.expire(10).is_empty());
+    }
+
+    #[test]
+    fn rejects_orders_beyond_the_concentration_limit() {
+        let mut book = OrderBook::from_initial_price(Price(U256::from(100)))
+            .with_concentration_limit(ConcentrationLimit {
+                levels: 2,
+                max_share_bps: 5_000,
+                min_depth: Quantity(U256::from(4)),
+            });
+        // below the minimum depth anyone may seed the book
+        book.add_order(limit_ask(U256::from(2), U256::from(101)), 0)
+            .unwrap();
+        let mut other = limit_ask(U256::from(2), U256::from(102));
+        other.owner = "other".to_string();
+        book.add_order(other, 0).unwrap();
+
+        let err = book
+            .add_order(limit_ask(U256::from(1), U256::from(101)), 0)
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Owner would exceed the maker concentration limit"
+        );
+        // outside the top two levels
+        book.add_order(limit_ask(U256::from(1), U256::from(103)), 0)
+            .unwrap();
+    }
+
+    #[test]
+    fn cancels_an_owners_orders_by_side_and_price() {
+        let mut book = OrderBook::from_initial_price(Price(U256::from(100)));
+        for price in [101, 102, 103] {
+            book.add_order(limit_ask(U256::from(1), U256::from(price)), 0)
+                .unwrap();
+        }
+        let mut other = limit_ask(U256::from(1), U256::from(102));
+        other.owner = "other".to_string();
+        let (other, _) = book.add_order(other, 0).unwrap();
+        let (bid, _) = book
+            .add_order(limit_bid(U256::from(1), U256::from(99)), 0)
+            .unwrap();
+
+        let filter = CancelFilter::owner("owner")
+            .with_side(Side::Ask)
+            .with_min_price(Price(U256::from(102)));
+        let cancelled = book.cancel_all(&filter);
+        let prices: Vec<_> = cancelled.iter().map(|order| order.limit_price).collect();
+        assert_eq!(
+            prices,
+            [102, 103].map(|price| Some(Price(U256::from(price))))
+        );
+        assert!(book.order(cancelled[0].id).is_none());
+        assert!(book.order(other).is_some());
+        assert!(book.order(bid).is_some());
+        assert!(!book.asks.contains_key(&Price(U256::from(103))));
+        assert_eq!(book.asks.len(), 2);
+    }
+
+    #[test]
+    fn owner_index_follows_orders_off_the_book() {
+        let mut book = OrderBook::from_initial_price(Price(U256::from(100)));
+        let owned_ids = |book: &OrderBook| {
+            let mut ids: Vec<_> = book
+                .owner_orders
+                .get("owner")
+                .into_iter()
+                .flatten()
+                .collect();
+            ids.sort();
+            ids.into_iter().copied().collect::<Vec<_>>()
+        };
+        let (filled, _) = book
+            .add_order(limit_bid(U256::from(1), U256::from(99)), 0)
+            .unwrap();
+        let mut expiring = limit_bid(U256::from(1), U256::from(98));
+        expiring.time_in_force = TimeInForce::GoodTilDate;
+        expiring.expire_timestamp = 5;
+        book.add_order(expiring, 0).unwrap();
+        let (amended, _) = book
+            .add_order(limit_bid(U256::from(1), U256::from(97)), 0)
+            .unwrap();
+        let (ask, _) = book
+            .add_order(limit_ask(U256::from(2), U256::from(105)), 0)
+            .unwrap();
+        let mut other = limit_ask(U256::from(1), U256::from(104));
+        other.owner = "other".to_string();
+        book.add_order(other, 0).unwrap();
+
+        book.add_order(market_ask(U256::from(1)), 0).unwrap();
+        book.match_market_orders(0);
+        book.expire(10);
+        book.amend(amended, Price(U256::from(96)), Quantity(U256::from(1)), 10)
+            .unwrap();
+        assert_eq!(owned_ids(&book), [amended, ask]);
+
+        let unordered = CancelFilter::owner("owner")
+            .with_min_price(Price(U256::from(105)))
+            .with_max_price(Price(U256::from(96)));
+        assert!(book.cancel_all(&unordered).is_empty());
+        book.add_order(limit_ask(U256::from(1), U256::from(103)), 10)
+            .unwrap();
+        // stops and queued market orders are indexed but never cancelled
+        let (stop, _) = book
+            .add_order(stop_ask(U256::from(1), U256::from(90)), 10)
+            .unwrap();
+        let cancelled = book.cancel_all(&CancelFilter::owner("owner"));
+        let prices: Vec<_> = cancelled.iter().map(|order| order.limit_price).collect();
+        assert_eq!(
+            prices,
+            [96, 103, 105].map(|price| Some(Price(U256::from(price))))
+        );
+        assert_eq!(owned_ids(&book), [stop]);
+        book.cancel(stop).unwrap();
+        assert!(owned_ids(&book).is_empty());
+        assert!(book.order(filled).is_none());
+        assert_eq!(book.owner_orders.len(), 1);
+    }
+
+    #[test]
+    fn depth_aggregates_visible_quantity_per_level() {
+        let mut book = OrderBook::from_initial_price(Price(U256::from(100)));
+        for price in [98, 99, 99] {
+            book.add_order(limit_bid(U256::from(2), U256::from(price)), 0)
+                .unwrap();
+        }
+        let mut iceberg = limit_ask(U256::from(10), U256::from(101));
+        iceberg.display_quantity = Quantity(U256::from(3));
+        book.add_order(iceberg, 0).unwrap();
+
+        let depth = book.depth(1);
+        assert_eq!(
+            depth.bids,
+            [DepthLevel {
+                price: Price(U256::from(99)),
+                quantity: Quantity(U256::from(4)),
+                order_count: 2,
+            }]
+        );
+        assert_eq!(depth.asks[0].quantity, Quantity(U256::from(3)));
+        assert_eq!(book.depth(5).bids.len(), 2);
+        let empty = book.depth(0);
+        assert!(empty.bids.is_empty() && empty.asks.is_empty());
+        assert_eq!(book.depth_at(Side::Ask, Price(U256::from(99))), None);
+        assert_eq!(book.depth_at(Side::Bid, Price(U256::from(97))), None);
+    }
+
+    #[test]
+    fn snapshot_round_trips_through_serde() {
+        let mut book = OrderBook::from_initial_price(Price(U256::from(100)));
+        book.add_order(limit_ask(U256::from(2), U256::from(101)), 0)
+            .unwrap();
+        book.add_oco(
+            limit_bid(U256::from(1), U256::from(99)),
+            stop_ask(U256::from(1), U256::from(95)),
+            0,
+        )
+        .unwrap();
+        book.add_order(market_bid(U256::from(3)), 0).unwrap();
+        book.match_market_orders(0);
+
+        let json = serde_json::to_string(&book.snapshot()).unwrap();
+        let mut restored = <OrderBook>::restore(serde_json::from_str(&json).unwrap()).unwrap();
+        assert_eq!(restored.snapshot(), book.snapshot());
+        assert_eq!(restored.market_bids, book.market_bids);
+        assert_eq!(restored.index.len(), book.index.len());
+        assert_eq!(restored.owner_orders, book.owner_orders);
+
+        let (id, _) = restored
+            .add_order(limit_ask(U256::from(1), U256::from(101)), 0)
+            .unwrap();
+        assert_eq!(id, OrderId(5));
+    }
+
+    #[test]
+    fn event_numbers_survive_truncation_and_restores() {
+        let mut book = OrderBook::from_initial_price(Price(U256::from(100)));
+        book.add_order(limit_ask(U256::from(1), U256::from(101)), 0)
+            .unwrap();
+        book.add_order(limit_bid(U256::from(1), U256::from(99)), 0)
+            .unwrap();
+        assert_eq!(book.next_event_sequence(), 4);
+        assert!(matches!(
+            book.events_since(3).unwrap(),
+            [Event::Rested { .. }]
+        ));
+
+        book.truncate_events_before(2);
+        assert_eq!(book.first_event_sequence(), 2);
+        assert_eq!(book.events().len(), 2);
+        assert_eq!(book.events_since(2).unwrap(), book.events());
+        assert_eq!(
+            book.events_since(1).unwrap_err().to_string(),
+            "Events from 1 were truncated; the oldest held is 2"
+        );
+        assert!(book.events_since(9).unwrap().is_empty());
+        book.truncate_events_before(9);
+        assert!(book.events().is_empty());
+        assert_eq!(book.first_event_sequence(), 4);
+
+        let mut restored = <OrderBook>::restore(book.snapshot()).unwrap();
+        assert_eq!(restored.next_event_sequence(), 4);
+        restored.cancel_all(&CancelFilter::owner("owner"));
+        assert_eq!(restored.events_since(4).unwrap().len(), 2);
+        assert_eq!(restored.next_event_sequence(), 6);
+    }
+
+    #[test]
+    fn restore_rejects_inconsistent_snapshots() {
+        let mut book = OrderBook::from_initial_price(Price(U256::from(100)));
+        book.add_oco(
+            limit_bid(U256::from(1), U256::from(99)),
+            stop_ask(U256::from(1), U256::from(95)),
+            0,
+        )
+        .unwrap();
+        let snapshot = book.snapshot();
+        let (slot, bid) = snapshot.limit_orders[0].clone();
+        let reject = |edit: &dyn Fn(&mut BookSnapshot)| {
+            let mut snapshot = snapshot.clone();
+            edit(&mut snapshot);
+            <OrderBook>::restore(snapshot).err().unwrap()
+        };
+
+        let err = reject(&|snapshot| {
+            snapshot.limit_orders[0].1.filled_quantity = Quantity(U256::from(2))
+        });
+        assert_eq!(
+            err,
+            OrderError::Invalid(bid.id, "Filled quantity exceeds order quantity".to_string())
+        );
+        assert_eq!(
+            reject(&|snapshot| snapshot.stop_orders[0].1.stop_price = None),
+            OrderError::Invalid(OrderId(2), "Order prices do not match its type".to_string())
+        );
+        assert_eq!(
+            reject(&|snapshot| snapshot.market_orders.push((slot + 1, bid.clone()))),
+            OrderError::WrongQueue(bid.id)
+        );
+        assert_eq!(
+            reject(&|snapshot| {
+                let stop = snapshot.stop_orders.pop().unwrap();
+                snapshot.limit_orders.push(stop);
+            }),
+            OrderError::WrongQueue(OrderId(2))
+        );
+        assert_eq!(
+            reject(&|snapshot| snapshot.stop_orders[0].1.id = bid.id),
+            OrderError::DuplicateOrder(bid.id)
+        );
+        assert_eq!(
+            reject(&|snapshot| snapshot.stop_orders[0].0 = slot),
+            OrderError::DuplicateSlot(slot)
+        );
+        assert_eq!(
+            reject(&|snapshot| snapshot.next_order_id = bid.id.0),
+            OrderError::OrderIdOutOfRange(bid.id)
+        );
+        assert_eq!(
+            reject(&|snapshot| snapshot.next_slot = slot),
+            OrderError::SlotOutOfRange(slot)
+        );
+        assert_eq!(
+            reject(&|snapshot| snapshot.links[0].1 = OrderId(9)),
+            OrderError::DanglingLink(OrderId(9))
+        );
+        assert_eq!(
+            reject(&|snapshot| snapshot.links.push(snapshot.links[0])),
+            OrderError::DanglingLink(bid.id)
+        );
+        assert!(<OrderBook>::restore(snapshot).is_ok());
+    }
+
+    #[test]
+    fn cancels_orders_by_id() {
+        let mut book = OrderBook::from_initial_price(Price(U256::from(100)));
+        let (ask, _) = book
+            .add_order(limit_ask(U256::from(2), U256::from(101)), 0)
+            .unwrap();
+        let (stop, _) = book
+            .add_order(stop_bid(U256::from(1), U256::from(105)), 0)
+            .unwrap();
+        let (other_ask, _) = book
+            .add_order(limit_ask(U256::from(1), U256::from(102)), 0)
+            .unwrap();
+        assert_ne!(ask, other_ask);
+
+        assert_eq!(book.cancel(ask).unwrap().id, ask);
+        assert!(!book.asks.contains_key(&Price(U256::from(101))));
+        assert_eq!(book.cancel(ask).unwrap_err().to_string(), "Order not found");
+        assert_eq!(
+            book.cancel(stop).unwrap().stop_price,
+            Some(Price(U256::from(105)))
+        );
+        assert!(book.stop_bids.is_empty());
+
+        // filled makers leave the index with the book
+        book.add_order(market_bid(U256::from(1)), 0).unwrap();
+        book.match_market_orders(0);
+        assert!(book.order(other_ask).is_none());
+        assert!(book.cancel(other_ask).is_err());
+        assert!(book.index.is_empty());
+    }
+
+    #[test]
+    fn amend_keeps_priority_only_for_quantity_decreases() {
+        let mut book = OrderBook::from_initial_price(Price(U256::from(100)));
+        let (first, _) = book
+            .add_order(limit_ask(U256::from(3), U256::from(101)), 0)
+            .unwrap();
+        let (second, _) = book
+            .add_order(limit_ask(U256::from(3), U256::from(101)), 0)
+            .unwrap();
+        let oldest = |book: &OrderBook| {
+            book.asks[&Price(U256::from(101))]
+                .values()
+                .next()
+                .unwrap()
+                .id
+        };
+
+        book.amend(first, Price(U256::from(101)), Quantity(U256::from(2)), 0)
+            .unwrap();
+        assert_eq!(oldest(&book), first);
+        assert_eq!(book.order(first).unwrap().quantity, Quantity(U256::from(2)));
+
+        book.amend(first, Price(U256::from(101)), Quantity(U256::from(4)), 0)
+            .unwrap();
+        assert_eq!(oldest(&book), second);
+
+        book.add_order(limit_bid(U256::from(3), U256::from(99)), 0)
+            .unwrap();
+        let Execution { taker, makers, .. } = book
+            .amend(second, Price(U256::from(99)), Quantity(U256::from(3)), 0)
+            .unwrap()
+            .unwrap();
+        assert_eq!(taker.id, second);
+        assert_eq!(taker.filled_quantity, Quantity(U256::from(3)));
+        assert_eq!(makers.len(), 1);
+        assert!(book.order(second).is_none());
+
+        let err = book
+            .amend(first, Price(U256::from(101)), Quantity::ZERO, 0)
+            .unwrap_err();
+        assert_eq!(err.to_string(), "Order quantity is zero");
+    }
+
+    #[test]
+    fn one_tick_improvement_retains_priority() {
+        let mut book = OrderBook::from_initial_price(Price(U256::from(100)))
+            .with_priority_retention(PriorityRetention {
+                tick_size: Price(U256::from(1)),
+                retained_bps: 10_000,
+            });
+        let (improving, _) = book
+            .add_order(limit_ask(U256::from(1), U256::from(101)), 0)
+            .unwrap();
+        let (resting, _) = book
+            .add_order(limit_ask(U256::from(1), U256::from(100)), 0)
+            .unwrap();
+        let (far, _) = book
+            .add_order(limit_ask(U256::from(1), U256::from(102)), 0)
+            .unwrap();
+        let oldest = |book: &OrderBook| {
+            book.asks[&Price(U256::from(100))]
+                .values()
+                .next()
+                .unwrap()
+                .id
+        };
+
+        book.amend(
+            improving,
+            Price(U256::from(100)),
+            Quantity(U256::from(1)),
+            0,
+        )
+        .unwrap();
+        assert_eq!(oldest(&book), improving);
+
+        // two ticks is a plain cancel-replace
+        book.amend(far, Price(U256::from(100)), Quantity(U256::from(1)), 0)
+            .unwrap();
+        assert_eq!(
+            book.asks[&Price(U256::from(100))]
+                .values()
+                .last()
+                .unwrap()
+                .id,
+            far
+        );
+        assert_eq!(
+            book.order(resting).unwrap().limit_price,
+            Some(Price(U256::from(100)))
+        );
+    }
+
+    #[test]
+    fn rejects_post_only_orders_that_would_cross() {
+        let mut book = OrderBook::from_initial_price(Price(U256::from(100)));
+        book.add_order(limit_ask(U256::from(1), U256::from(101)), 0)
+            .unwrap();
+
+        let mut post_only = limit_bid(U256::from(1), U256::from(101));
+        post_only.post_only = true;
+        let err = book.add_order(post_only.clone(), 0).unwrap_err();
+        assert_eq!(err.to_string(), "Post-only order would take liquidity");
+        assert!(book.asks.contains_key(&Price(U256::from(101))));
+
+        post_only.limit_price = Some(Price(U256::from(100)));
+        let (id, _) = book.add_order(post_only, 0).unwrap();
+        let err = book
+            .amend(id, Price(U256::from(101)), Quantity(U256::from(1)), 0)
+            .unwrap_err();
+        assert_eq!(err.to_string(), "Post-only order would take liquidity");
+        assert_eq!(
+            book.order(id).unwrap().limit_price,
+            Some(Price(U256::from(100)))
+        );
+    }
+
+    #[test]
+    fn one_cancels_other_pairs() {
+        let mut book = OrderBook::from_initial_price(Price(U256::from(100)));
+        let ((take_profit, stop_loss), executions) = book
+            .add_oco(
+                limit_ask(U256::from(1), U256::from(110)),
+                stop_ask(U256::from(1), U256::from(95)),
+                0,
+            )
+            .unwrap();
+        assert!(executions.is_empty());
+
+        book.add_order(limit_bid(U256::from(1), U256::from(110)), 0)
+            .unwrap();
+        let cancelled = book.take_linked_cancellations();
+        assert_eq!(cancelled.len(), 1);
+        assert_eq!(cancelled[0].0, take_profit);
+        assert_eq!(cancelled[0].1.id, stop_loss);
+        assert!(book.stop_asks.is_empty());
+
+        // two legs on the same side never both fill in one sweep
+        let ((first, second), _) = book
+            .add_oco(
+                limit_ask(U256::from(1), U256::from(101)),
+                limit_ask(U256::from(1), U256::from(102)),
+                0,
+            )
+            .unwrap();
+        let Execution { taker, makers, .. } = book
+            .add_order(limit_bid(U256::from(2), U256::from(102)), 0)
+            .unwrap()
+            .1
+            .unwrap();
+        assert_eq!(taker.filled_quantity, Quantity(U256::from(1)));
+        assert_eq!(makers[0].id, first);
+        assert_eq!(book.take_linked_cancellations()[0].1.id, second);
+        assert!(book.asks.is_empty());
+        assert!(book.links.is_empty());
+    }
+
+    #[test]
+    fn market_legs_never_fill_their_own_partner() {
+        let mut book = OrderBook::from_initial_price(Price(U256::from(100)));
+        let ((ask, bid), executions) = book
+            .add_oco(
+                limit_ask(U256::from(1), U256::from(100)),
+                market_bid(U256::from(1)),
+                0,
+            )
+            .unwrap();
+        assert!(executions.is_empty());
+        assert!(book.match_market_orders(0).is_empty());
+        assert!(book
+            .preview_order(&book.market_bids[&book.index[&bid].slot], 0)
+            .unwrap()
+            .fills
+            .is_empty());
+
+        let mut other = limit_ask(U256::from(1), U256::from(101));
+        other.owner = "other".to_string();
+        book.add_order(other, 0).unwrap();
+        let matches = book.match_market_orders(0);
+        assert_eq!(matches.len(), 1);
+        assert_eq!(matches[0].taker.id, bid);
+        assert_eq!(book.take_linked_cancellations()[0].1.id, ask);
+        assert!(book.asks.is_empty() && book.market_bids.is_empty());
+    }
+
+    #[test]
+    fn lazily_expired_legs_are_unlinked() {
+        let mut book = OrderBook::from_initial_price(Price(U256::from(100)));
+        let mut stop = stop_ask(U256::from(1), U256::from(95));
+        stop.time_in_force = TimeInForce::GoodTilDate;
+        stop.expire_timestamp = 5;
+        let ((bid, _), _) = book
+            .add_oco(limit_bid(U256::from(1), U256::from(90)), stop, 0)
+            .unwrap();
+        let mut taker = market_bid(U256::from(1));
+        taker.time_in_force = TimeInForce::GoodTilDate;
+        taker.expire_timestamp = 5;
+        book.add_oco(limit_ask(U256::from(1), U256::from(120)), taker, 0)
+            .unwrap();
+
+        // both expire when the queues they wait in are next walked
+        book.add_order(limit_ask(U256::from(1), U256::from(95)), 10)
+            .unwrap();
+        book.add_order(limit_bid(U256::from(1), U256::from(95)), 10)
+            .unwrap();
+        book.match_market_orders(10);
+        book.trigger_stops(10);
+        assert_eq!(book.expired_orders.len(), 2);
+        assert!(book.links.is_empty());
+        assert!(book.order(bid).is_some());
+    }
+
+    #[test]
+    fn rejects_market_orders_beyond_queue_depth() {
+        let mut book =
+            OrderBook::from_initial_price(Price(U256::from(100))).with_max_market_queue_depth(1);
+        book.add_order(market_bid(U256::from(1)), 0).unwrap();
+        let err = book.add_order(market_bid(U256::from(1)), 0).unwrap_err();
+        assert_eq!(err.to_string(), "Market order queue is full");
+        assert_eq!(book.market_bids.len(), 1);
+    }
+
+    #[test]
+    fn sorted_ladder_books_match_like_tree_books() {
+        fn run<L: PriceLadder>() -> (BookSnapshot, Vec<Event>) {
+            let mut book = OrderBook::<L>::with_ladder(Price(U256::from(100)));
+            for price in [103, 101, 105, 102] {
+                book.add_order(limit_ask(U256::from(2), U256::from(price)), 0)
+                    .unwrap();
+                book.add_order(limit_bid(U256::from(2), U256::from(price - 5)), 0)
+                    .unwrap();
+            }
+            book.add_order(stop_bid(U256::from(1), U256::from(102)), 0)
+                .unwrap();
+            let (cancelled, _) = book
+                .add_order(limit_ask(U256::from(1), U256::from(104)), 0)
+                .unwrap();
+            book.cancel(cancelled).unwrap();
+            book.add_order(limit_bid(U256::from(5), U256::from(102)), 0)
+                .unwrap();
+            book.trigger_stops(0);
+            book.add_order(market_ask(U256::from(3)), 0).unwrap();
+            book.compact();
+            (book.snapshot(), book.events().to_vec())
+        }
+
+        let (snapshot, events) = run::<crate::ladder::SortedLadder>();
+        assert_eq!((snapshot, events), run::<BTreeMap<Price, PriceLevel>>());
+    }
+}
@@ -0,0 +1,77 @@
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
+
+/// Source of time for the engine. Monotonic time is for measuring
+/// latencies; wall-clock time is for expirations and funding.
+pub trait Clock {
+    fn monotonic(&self) -> Duration;
+    fn unix_timestamp(&self) -> u64;
+}
+
+/// Clock backed by the operating system.
+pub struct SystemClock {
+    started_at: Instant,
+}
+
+impl SystemClock {
+    pub fn new() -> Self {
+        Self {
+            started_at: Instant::now(),
+        }
+    }
+}
+
+impl Default for SystemClock {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Clock for SystemClock {
+    fn monotonic(&self) -> Duration {
+        self.started_at.elapsed()
+    }
+
+    fn unix_timestamp(&self) -> u64 {
+        SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_secs())
+            .unwrap_or_default()
+    }
+}
+
+/// Clock that only moves when told to, for deterministic tests and replays.
+#[derive(Default)]
+pub struct ManualClock {
+    monotonic_nanos: AtomicU64,
+    unix_nanos: AtomicU64,
+}
+
+impl ManualClock {
+    pub fn at_unix_timestamp(unix_timestamp: u64) -> Self {
+        let clock = Self::default();
+        clock.set_unix_timestamp(unix_timestamp);
+        clock
+    }
+
+    pub fn advance(&self, by: Duration) {
+        let nanos = by.as_nanos() as u64;
+        self.monotonic_nanos.fetch_add(nanos, Ordering::Relaxed);
+        self.unix_nanos.fetch_add(nanos, Ordering::Relaxed);
+    }
+
+    pub fn set_unix_timestamp(&self, unix_timestamp: u64) {
+        self.unix_nanos
+            .store(unix_timestamp * 1_000_000_000, Ordering::Relaxed);
+    }
+}
+
+impl Clock for ManualClock {
+    fn monotonic(&self) -> Duration {
+        Duration::from_nanos(self.monotonic_nanos.load(Ordering::Relaxed))
+    }
+
+    fn unix_timestamp(&self) -> u64 {
+        self.unix_nanos.load(Ordering::Relaxed) / 1_000_000_000
+    }
+}
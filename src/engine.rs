@@ -0,0 +1,710 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::Arc;
+use std::time::Duration;
+
+use alloy::primitives::{keccak256, B256};
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::book::{BookSnapshot, CancelFilter, OrderBook};
+use crate::clock::{Clock, SystemClock};
+use crate::market::MarketState;
+use crate::matching::{Execution, Taker};
+use crate::order::{Order, OrderId, OrderType};
+use crate::units::{Price, Quantity};
+use crate::wal::{WalEntry, WriteAheadLog};
+
+/// A change to the book. The engine's methods each run one, and
+/// `Engine::schedule` takes them directly.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub enum Command {
+    Submit(Order),
+    SubmitOco(Box<(Order, Order)>),
+    /// A parent order and a child submitted once the parent fills.
+    SubmitDependent(Box<(Order, Order)>),
+    Cancel(OrderId),
+    CancelAll(CancelFilter),
+    Amend {
+        order_id: OrderId,
+        new_price: Price,
+        new_quantity: Quantity,
+    },
+    Expire,
+    /// Moves the market to another trading phase.
+    Transition(MarketState),
+}
+
+/// What a scheduled command did once the engine ran it.
+#[derive(Clone, Debug)]
+pub enum CommandResult {
+    Submitted(OrderId, Vec<Execution>),
+    SubmittedOco((OrderId, OrderId), Vec<Execution>),
+    SubmittedDependent(OrderId, Vec<Execution>),
+    Cancelled(Box<Order>),
+    CancelledAll(Vec<Order>),
+    Amended(Vec<Execution>),
+    Expired(Vec<Order>),
+    /// Queued market orders and stops that ran on returning to continuous
+    /// trading.
+    Transitioned(Vec<Execution>),
+}
+
+/// Operator risk rule the engine runs before every command, against the
+/// book as it stands when the command would run and the unix time `now` it
+/// would run at. Returning an error rejects the command with that error.
+pub trait PreTradeFilter {
+    fn check(&self, command: &Command, book: &OrderBook, now: u64) -> Result<()>;
+}
+
+/// Lets the operator keep a handle to a filter the engine owns.
+impl<F: PreTradeFilter> PreTradeFilter for Arc<F> {
+    fn check(&self, command: &Command, book: &OrderBook, now: u64) -> Result<()> {
+        (**self).check(command, book, now)
+    }
+}
+
+/// Checkpoint of an engine, for resuming it with `Engine::restore` and
+/// replaying the write-ahead log entries logged after it.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct EngineSnapshot {
+    pub book: BookSnapshot,
+    pub state: MarketState,
+    /// Orders waiting for their parent, by parent id, in id order.
+    pub children: Vec<(OrderId, Order)>,
+    /// Sequence number of the first log entry the snapshot doesn't cover;
+    /// recovery replays from here.
+    pub wal_sequence: u64,
+}
+
+/// Owns a market's order book and drives matching for submitted orders,
+/// reading the time from `C`.
+pub struct Engine<C: Clock = SystemClock> {
+    book: OrderBook,
+    clock: C,
+    /// Delay applied to scheduled commands that would take liquidity.
+    speed_bump: Option<Duration>,
+    /// Commands held back by the speed bump with the monotonic time they
+    /// become due, oldest first.
+    delayed: VecDeque<(Duration, Command)>,
+    /// How often `run_due` expires good-til-date orders, and the monotonic
+    /// time it next does.
+    expiry_interval: Option<Duration>,
+    next_expiry: Duration,
+    /// Run in the order they were added; the first rejection wins.
+    filters: Vec<Box<dyn PreTradeFilter>>,
+    /// Where commands are recorded before they are applied.
+    wal: Option<WriteAheadLog>,
+    /// Sequence number of the log entry after the last one applied.
+    wal_sequence: u64,
+    state: MarketState,
+    /// Orders waiting for their parent, by the parent's id.
+    children: HashMap<OrderId, Order>,
+}
+
+impl Engine {
+    pub fn new(book: OrderBook) -> Self {
+        Self::with_clock(book, SystemClock::new())
+    }
+
+    pub fn from_initial_price(initial_price: Price) -> Self {
+        Self::new(OrderBook::from_initial_price(initial_price))
+    }
+}
+
+impl<C: Clock> Engine<C> {
+    pub fn with_clock(book: OrderBook, clock: C) -> Self {
+        Self {
+            book,
+            clock,
+            speed_bump: None,
+            delayed: VecDeque::new(),
+            expiry_interval: None,
+            next_expiry: Duration::ZERO,
+            filters: Vec::new(),
+            wal: None,
+            wal_sequence: 0,
+            state: MarketState::default(),
+            children: HashMap::new(),
+        }
+    }
+
+    /// Delays scheduled commands that would trade on arrival by `delay`,
+    /// so resting orders can be cancelled or amended away first.
+    pub fn with_speed_bump(mut self, delay: Duration) -> Self {
+        self.speed_bump = Some(delay);
+        self
+    }
+
+    /// Has `run_due` expire good-til-date orders every `interval`.
+    /// Without it the book only drops an expired order when matching walks
+    /// past it, so callers must `expire` on their own schedule. Each run is
+    /// an `Expire` command and is logged like any other.
+    pub fn with_expiry_interval(mut self, interval: Duration) -> Self {
+        self.expiry_interval = Some(interval);
+        self
+    }
+
+    /// Adds a pre-trade filter every command must pass.
+    pub fn with_filter(mut self, filter: impl PreTradeFilter + 'static) -> Self {
+        self.filters.push(Box::new(filter));
+        self
+    }
+
+    /// Resumes from `snapshot`. `book` is `snapshot.book` brought back with
+    /// `OrderBook::restore` and configured as it was, since the book's
+    /// settings aren't part of snapshots. Fails if it holds anything else,
+    /// or if a waiting child's parent isn't on it.
+    pub fn restore(book: OrderBook, snapshot: EngineSnapshot, clock: C) -> Result<Self> {
+        if book.snapshot() != snapshot.book {
+            bail!("Book does not match the snapshot");
+        }
+        if let Some((parent_id, _)) = snapshot
+            .children
+            .iter()
+            .find(|(parent_id, _)| book.order(*parent_id).is_none())
+        {
+            bail!("Parent order {} is not on the book", parent_id.0);
+        }
+        Ok(Self {
+            wal_sequence: snapshot.wal_sequence,
+            state: snapshot.state,
+            children: snapshot.children.into_iter().collect(),
+            ..Self::with_clock(book, clock)
+        })
+    }
+
+    /// Records every command that passes the filters in `wal`, with the
+    /// time it ran, before applying it. To recover, `restore` the latest
+    /// `snapshot` and `replay` the log from its `wal_sequence`.
+    pub fn with_wal(mut self, wal: WriteAheadLog) -> Self {
+        self.wal = Some(wal);
+        self
+    }
+
+    pub fn book(&self) -> &OrderBook {
+        &self.book
+    }
+
+    /// Checkpoints the engine, along with how much of its write-ahead log
+    /// the checkpoint covers.
+    pub fn snapshot(&self) -> EngineSnapshot {
+        let mut children: Vec<_> = self
+            .children
+            .iter()
+            .map(|(parent_id, child)| (*parent_id, child.clone()))
+            .collect();
+        children.sort_by_key(|(parent_id, _)| *parent_id);
+        EngineSnapshot {
+            book: self.book.snapshot(),
+            state: self.state,
+            children,
+            wal_sequence: self.wal_sequence,
+        }
+    }
+
+    /// Keccak-256 hash of the engine's snapshot. Replicas that applied the
+    /// same log entries have the same hash.
+    pub fn state_hash(&self) -> B256 {
+        let snapshot = serde_json::to_vec(&self.snapshot()).expect("snapshots serialize");
+        keccak256(snapshot)
+    }
+
+    pub fn clock(&self) -> &C {
+        &self.clock
+    }
+
+    /// The market's trading phase; a new engine starts in continuous
+    /// trading.
+    pub fn state(&self) -> MarketState {
+        self.state
+    }
+
+    /// Moves the market to `next`, if the current state allows it. Queued
+    /// market orders and stops only run in continuous trading, so they
+    /// catch up on returning to it.
+    pub fn transition(&mut self, next: MarketState) -> Result<Vec<Execution>> {
+        match self.run(Command::Transition(next))? {
+            CommandResult::Transitioned(matches) => Ok(matches),
+            _ => unreachable!(),
+        }
+    }
+
+    /// Adds `order` to the book, runs matching until no queued market order
+    /// can execute, then activates any stop orders the resulting trades
+    /// triggered. Returns the id assigned to `order` and each taker with the
+    /// makers it traded against, in execution order.
+    pub fn submit(&mut self, order: Order) -> Result<(OrderId, Vec<Execution>)> {
+        match self.run(Command::Submit(order))? {
+            CommandResult::Submitted(order_id, matches) => Ok((order_id, matches)),
+            _ => unreachable!(),
+        }
+    }
+
+    /// Adds a one-cancels-other pair, then runs follow-on matching the way
+    /// `submit` does.
+    pub fn submit_oco(
+        &mut self,
+        first: Order,
+        second: Order,
+    ) -> Result<((OrderId, OrderId), Vec<Execution>)> {
+        match self.run(Command::SubmitOco(Box::new((first, second))))? {
+            CommandResult::SubmittedOco(order_ids, matches) => Ok((order_ids, matches)),
+            _ => unreachable!(),
+        }
+    }
+
+    /// Adds `parent` and holds `child` until the parent fills completely,
+    /// then submits it in the same follow-on pass as stop triggering, so
+    /// an entry can place its own take-profit. The child trades for the
+    /// parent's owner and is dropped if the parent leaves the book
+    /// unfilled or the book rejects it on release. Pre-trade filters see
+    /// both orders when the pair is submitted.
+    pub fn submit_dependent(
+        &mut self,
+        parent: Order,
+        child: Order,
+    ) -> Result<(OrderId, Vec<Execution>)> {
+        match self.run(Command::SubmitDependent(Box::new((parent, child))))? {
+            CommandResult::SubmittedDependent(order_id, matches) => Ok((order_id, matches)),
+            _ => unreachable!(),
+        }
+    }
+
+    /// The child order waiting on `parent_id` to fill, if any.
+    pub fn dependent(&self, parent_id: OrderId) -> Option<&Order> {
+        self.children.get(&parent_id)
+    }
+
+    /// Orders cancelled by their one-cancels-other partner trading or
+    /// triggering since the last call, paired with the partner's id.
+    pub fn take_linked_cancellations(&mut self) -> Vec<(OrderId, Order)> {
+        self.book.take_linked_cancellations()
+    }
+
+    /// Market orders cancelled by price protection since the last call.
+    pub fn take_price_band_cancellations(&mut self) -> Vec<Order> {
+        self.book.take_price_band_cancellations()
+    }
+
+    /// Orders cancelled by self-trade prevention since the last call.
+    pub fn take_self_trade_cancellations(&mut self) -> Vec<Order> {
+        self.book.take_self_trade_cancellations()
+    }
+
+    pub fn cancel(&mut self, order_id: OrderId) -> Result<Order> {
+        match self.run(Command::Cancel(order_id))? {
+            CommandResult::Cancelled(order) => Ok(*order),
+            _ => unreachable!(),
+        }
+    }
+
+    /// Cancels the resting limit orders `filter` selects in one step.
+    pub fn cancel_all(&mut self, filter: CancelFilter) -> Result<Vec<Order>> {
+        match self.run(Command::CancelAll(filter))? {
+            CommandResult::CancelledAll(orders) => Ok(orders),
+            _ => unreachable!(),
+        }
+    }
+
+    /// Amends a resting limit order, then runs matching and stop triggering
+    /// as `submit` does in case the new price crossed the spread.
+    pub fn amend(
+        &mut self,
+        order_id: OrderId,
+        new_price: Price,
+        new_quantity: Quantity,
+    ) -> Result<Vec<Execution>> {
+        let command = Command::Amend {
+            order_id,
+            new_price,
+            new_quantity,
+        };
+        match self.run(command)? {
+            CommandResult::Amended(matches) => Ok(matches),
+            _ => unreachable!(),
+        }
+    }
+
+    /// Removes orders whose good-til-date expiry has passed and returns
+    /// them.
+    pub fn expire(&mut self) -> Result<Vec<Order>> {
+        match self.run(Command::Expire)? {
+            CommandResult::Expired(orders) => Ok(orders),
+            _ => unreachable!(),
+        }
+    }
+
+    /// Runs delayed commands that have come due, then `command` unless the
+    /// speed bump holds it back. Cancels, and submits or amends that would
+    /// only rest, always run straight away; anything that would trade on
+    /// arrival waits for the speed bump. Returns results in the order the
+    /// commands ran.
+    pub fn schedule(&mut self, command: Command) -> Vec<Result<CommandResult>> {
+        let mut results = self.run_due();
+        match self.speed_bump {
+            Some(delay) if self.takes_liquidity(&command) => {
+                let due = self.clock.monotonic() + delay;
+                self.delayed.push_back((due, command));
+            }
+            _ => results.push(self.run(command)),
+        }
+        results
+    }
+
+    /// Expires orders if the expiry interval has elapsed, then runs the
+    /// delayed commands whose speed bump has elapsed.
+    pub fn run_due(&mut self) -> Vec<Result<CommandResult>> {
+        let now = self.clock.monotonic();
+        let mut results = Vec::new();
+        if let Some(interval) = self.expiry_interval {
+            if self.next_expiry <= now {
+                self.next_expiry = now + interval;
+                results.push(self.run(Command::Expire));
+            }
+        }
+        while self.delayed.front().is_some_and(|(due, _)| *due <= now) {
+            let (_, command) = self.delayed.pop_front().unwrap();
+            results.push(self.run(command));
+        }
+        results
+    }
+
+    /// Re-applies logged commands at the time they originally ran, without
+    /// screening or logging them again. Commands that failed the first time
+    /// fail the same way and are skipped, as are entries the engine has
+    /// already applied, such as those its snapshot covers.
+    pub fn replay(&mut self, entries: impl IntoIterator<Item = WalEntry>) {
+        for entry in entries {
+            if entry.sequence < self.wal_sequence {
+                continue;
+            }
+            self.wal_sequence = entry.sequence + 1;
+            let _ = self.apply(entry.command, entry.timestamp);
+        }
+    }
+
+    /// Screens `command`, writes it to the write-ahead log and applies it.
+    fn run(&mut self, command: Command) -> Result<CommandResult> {
+        self.admit(&command)?;
+        let now = self.clock.unix_timestamp();
+        for filter in &self.filters {
+            filter.check(&command, &self.book, now)?;
+        }
+        if let Some(wal) = &mut self.wal {
+            self.wal_sequence = wal.append(now, &command)? + 1;
+        }
+        self.apply(command, now)
+    }
+
+    /// Checks `command` against the market state. This is the one place
+    /// the state is enforced, ahead of the operator's filters.
+    fn admit(&self, command: &Command) -> Result<()> {
+        if let Command::Transition(next) = command {
+            if !self.state.can_transition_to(*next) {
+                bail!("Market cannot move from {:?} to {next:?}", self.state);
+            }
+        }
+        if !self.state.allows(command) {
+            bail!(
+                "Market is {:?} and does not accept this command",
+                self.state
+            );
+        }
+        if self.state == MarketState::PostOnlySession
+            && matches!(command, Command::Amend { .. })
+            && self.takes_liquidity(command)
+        {
+            bail!("Amend would take liquidity");
+        }
+        Ok(())
+    }
+
+    fn apply(&mut self, command: Command, now: u64) -> Result<CommandResult> {
+        let result = match command {
+            Command::Submit(order) => {
+                let (order_id, executed) = self.book.add_order(order, now)?;
+                let matches = self.follow_on(Vec::from_iter(executed), now);
+                CommandResult::Submitted(order_id, matches)
+            }
+            Command::SubmitOco(legs) => {
+                let (first, second) = *legs;
+                let (order_ids, executed) = self.book.add_oco(first, second, now)?;
+                CommandResult::SubmittedOco(order_ids, self.follow_on(executed, now))
+            }
+            Command::SubmitDependent(orders) => {
+                let (parent, mut child) = *orders;
+                child.owner = parent.owner.clone();
+                child.validate()?;
+                let (order_id, executed) = self.book.add_order(parent, now)?;
+                self.children.insert(order_id, child);
+                let matches = self.follow_on(Vec::from_iter(executed), now);
+                CommandResult::SubmittedDependent(order_id, matches)
+            }
+            Command::Cancel(order_id) => {
+                CommandResult::Cancelled(Box::new(self.book.cancel(order_id)?))
+            }
+            Command::CancelAll(filter) => {
+                CommandResult::CancelledAll(self.book.cancel_all(&filter))
+            }
+            Command::Amend {
+                order_id,
+                new_price,
+                new_quantity,
+            } => {
+                let executed = self.book.amend(order_id, new_price, new_quantity, now)?;
+                CommandResult::Amended(self.follow_on(Vec::from_iter(executed), now))
+            }
+            Command::Expire => CommandResult::Expired(self.book.expire(now)),
+            Command::Transition(next) => {
+                self.state = next;
+                CommandResult::Transitioned(self.follow_on(Vec::new(), now))
+            }
+        };
+        let book = &self.book;
+        self.children
+            .retain(|parent_id, _| book.order(*parent_id).is_some());
+        Ok(result)
+    }
+
+    /// Runs matching until no queued market order can execute, then
+    /// triggers stops and releases the children of filled parents, after a
+    /// command that may have traded. Released children can fill further
+    /// parents, so passes repeat until none is released. Outside continuous
+    /// trading none of this runs.
+    fn follow_on(&mut self, mut matches: Vec<Execution>, now: u64) -> Vec<Execution> {
+        if self.state != MarketState::Continuous {
+            return matches;
+        }
+        let mut checked = 0;
+        loop {
+            matches.extend(self.book.match_market_orders(now));
+            matches.extend(self.book.trigger_stops(now));
+            let released = self.release_children(&matches[checked..]);
+            checked = matches.len();
+            if released.is_empty() {
+                return matches;
+            }
+            for child in released {
+                if let Ok((_, executed)) = self.book.add_order(child, now) {
+                    matches.extend(executed);
+                }
+            }
+        }
+    }
+
+    /// Takes the children of the parents `matches` filled completely, in
+    /// the order the parents filled.
+    fn release_children(&mut self, matches: &[Execution]) -> Vec<Order> {
+        let mut released = Vec::new();
+        for execution in matches {
+            let orders = std::iter::once(&execution.taker).chain(&execution.makers);
+            for order in orders {
+                if order.filled_quantity == order.quantity {
+                    released.extend(self.children.remove(&order.id));
+                }
+            }
+        }
+        released
+    }
+
+    /// Whether `command` would trade against the book if it ran now.
+    /// Market and stop orders are always treated as takers, since they
+    /// execute whenever liquidity or the trigger price arrives.
+    fn takes_liquidity(&self, command: &Command) -> bool {
+        let now = self.clock.unix_timestamp();
+        let crosses = |order: &Order, limit_price, quantity: Quantity| {
+            let quantity = quantity.saturating_sub(order.filled_quantity);
+            let taker = Taker {
+                limit_price,
+                ..Taker::of(order)
+            };
+            !self.book.simulate(taker, quantity, now).is_empty()
+        };
+        let submit_takes = |order: &Order| match order.order_type {
+            OrderType::Limit => {
+                order.time_in_force.is_immediate()
+                    || crosses(order, order.price_bound(), order.quantity)
+            }
+            _ => true,
+        };
+        match command {
+            Command::Cancel(_)
+            | Command::CancelAll(_)
+            | Command::Expire
+            | Command::Transition(_) => false,
+            Command::Submit(order) => submit_takes(order),
+            Command::SubmitOco(legs) => submit_takes(&legs.0) || submit_takes(&legs.1),
+            Command::SubmitDependent(orders) => submit_takes(&orders.0),
+            Command::Amend {
+                order_id,
+                new_price,
+                new_quantity,
+            } => self
+                .book
+                .order(*order_id)
+                .is_some_and(|order| crosses(order, *new_price, *new_quantity)),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::clock::ManualClock;
+    use crate::order::{Side, TimeInForce};
+    use crate::test_utils::*;
+    use alloy::primitives::U256;
+
+    struct MaxQuantity(Quantity);
+
+    impl PreTradeFilter for MaxQuantity {
+        fn check(&self, command: &Command, _book: &OrderBook, _now: u64) -> Result<()> {
+            match command {
+                Command::Submit(order) if order.quantity > self.0 => {
+                    anyhow::bail!("Order exceeds the quantity cap")
+                }
+                _ => Ok(()),
+            }
+        }
+    }
+
+    #[test]
+    fn pre_trade_filters_reject_commands() {
+        let mut engine = Engine::from_initial_price(Price(U256::from(100)))
+            .with_filter(MaxQuantity(Quantity(U256::from(10))));
+
+        let err = engine
+            .submit(limit_ask(U256::from(11), U256::from(101)))
+            .unwrap_err();
+        assert_eq!(err.to_string(), "Order exceeds the quantity cap");
+        let (id, _) = engine
+            .submit(limit_ask(U256::from(10), U256::from(101)))
+            .unwrap();
+        assert!(engine.cancel(id).is_ok());
+    }
+
+    #[test]
+    fn children_are_submitted_once_their_parent_fills() {
+        let mut engine = Engine::from_initial_price(Price(U256::from(100)));
+        engine
+            .submit(limit_ask(U256::from(2), U256::from(101)))
+            .unwrap();
+        let mut take_profit = limit_ask(U256::from(2), U256::from(110));
+        take_profit.owner = "someone else".to_string();
+        let (entry, _) = engine
+            .submit_dependent(limit_bid(U256::from(3), U256::from(101)), take_profit)
+            .unwrap();
+        assert!(engine.dependent(entry).is_some());
+        assert!(engine
+            .book()
+            .depth_at(Side::Ask, Price(U256::from(110)))
+            .is_none());
+
+        let (_, matches) = engine
+            .submit(limit_ask(U256::from(1), U256::from(101)))
+            .unwrap();
+        assert_eq!(matches.len(), 1);
+        assert!(engine.dependent(entry).is_none());
+        let released = engine
+            .book()
+            .depth_at(Side::Ask, Price(U256::from(110)))
+            .unwrap();
+        assert_eq!(released.quantity, Quantity(U256::from(2)));
+
+        let (parent, _) = engine
+            .submit_dependent(
+                limit_bid(U256::from(1), U256::from(99)),
+                limit_ask(U256::from(1), U256::from(120)),
+            )
+            .unwrap();
+        engine.cancel(parent).unwrap();
+        assert!(engine.dependent(parent).is_none());
+    }
+
+    #[test]
+    fn waiting_children_survive_a_restore() {
+        let mut engine = Engine::from_initial_price(Price(U256::from(100)));
+        let (entry, _) = engine
+            .submit_dependent(
+                limit_bid(U256::from(1), U256::from(99)),
+                limit_ask(U256::from(1), U256::from(110)),
+            )
+            .unwrap();
+        let snapshot = engine.snapshot();
+        assert_eq!(snapshot.children.len(), 1);
+
+        let book = || OrderBook::restore(snapshot.book.clone()).unwrap();
+        let mut restored = Engine::restore(book(), snapshot.clone(), SystemClock::new()).unwrap();
+        assert_eq!(restored.state_hash(), engine.state_hash());
+        assert_eq!(restored.dependent(entry), engine.dependent(entry));
+        restored.submit(market_ask(U256::from(1))).unwrap();
+        assert!(restored.dependent(entry).is_none());
+        assert!(restored
+            .book()
+            .depth_at(Side::Ask, Price(U256::from(110)))
+            .is_some());
+
+        let mut orphaned = snapshot.clone();
+        orphaned.children[0].0 = OrderId(9);
+        let err = Engine::restore(book(), orphaned, SystemClock::new())
+            .err()
+            .unwrap();
+        assert_eq!(err.to_string(), "Parent order 9 is not on the book");
+    }
+
+    #[test]
+    fn speed_bump_delays_takers_but_not_cancels() {
+        let book = OrderBook::from_initial_price(Price(U256::from(100)));
+        let mut engine = Engine::with_clock(book, ManualClock::default())
+            .with_speed_bump(Duration::from_millis(5));
+
+        let results = engine.schedule(Command::Submit(limit_ask(U256::from(1), U256::from(101))));
+        let Ok(CommandResult::Submitted(ask, _)) = results[0] else {
+            panic!("resting order was delayed");
+        };
+        assert!(engine
+            .schedule(Command::Submit(market_bid(U256::from(1))))
+            .is_empty());
+        assert!(matches!(
+            engine.schedule(Command::Cancel(ask))[..],
+            [Ok(CommandResult::Cancelled(_))]
+        ));
+
+        engine.clock().advance(Duration::from_millis(5));
+        let results = engine.run_due();
+        assert!(matches!(
+            results[..],
+            [Ok(CommandResult::Submitted(_, ref matches))] if matches.is_empty()
+        ));
+    }
+
+    #[test]
+    fn run_due_expires_orders_on_its_interval() {
+        let book = OrderBook::from_initial_price(Price(U256::from(100)));
+        let mut engine = Engine::with_clock(book, ManualClock::default())
+            .with_expiry_interval(Duration::from_secs(10));
+        let mut expiring = limit_bid(U256::from(1), U256::from(99));
+        expiring.time_in_force = TimeInForce::GoodTilDate;
+        expiring.expire_timestamp = 5;
+        let (bid, _) = engine.submit(expiring).unwrap();
+
+        assert!(matches!(
+            engine.run_due()[..],
+            [Ok(CommandResult::Expired(ref orders))] if orders.is_empty()
+        ));
+        engine.clock().advance(Duration::from_secs(6));
+        assert!(engine.run_due().is_empty());
+        assert!(engine.book().order(bid).is_some());
+
+        engine.clock().advance(Duration::from_secs(4));
+        let results = engine.run_due();
+        assert!(matches!(
+            results[..],
+            [Ok(CommandResult::Expired(ref orders))] if orders[0].id == bid
+        ));
+        assert!(engine.book().order(bid).is_none());
+        assert!(Engine::from_initial_price(Price(U256::from(100)))
+            .run_due()
+            .is_empty());
+    }
+}
@@ -0,0 +1,262 @@
+use serde::{Deserialize, Serialize};
+
+use crate::book::{OrderBook, QueueKind};
+use crate::ladder::PriceLadder;
+use crate::matching::Trade;
+use crate::order::{Order, OrderId, OrderType};
+use crate::units::{Price, Quantity};
+
+/// Why an order left the book without trading or expiring.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum CancelReason {
+    /// Cancelled by its owner, alone or with `OrderBook::cancel_all`.
+    Requested,
+    /// Removed by a cancel-replace amend; the replacement follows.
+    Replaced,
+    /// Its one-cancels-other partner traded or triggered.
+    Linked,
+    SelfTrade,
+    PriceBand,
+}
+
+/// A change to the state of a book, in the order the book made it.
+/// Folding a book's events into a fresh book with `OrderBook::apply`
+/// rebuilds its queues, links and last traded price; configuration such as
+/// limits and the price band is not part of the log.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Event {
+    /// An order passed the book's checks and was assigned its id.
+    Accepted(Order),
+    /// Two accepted orders were paired as one-cancels-other.
+    Linked(OrderId, OrderId),
+    /// An order joined a queue at `slot`, as it stood when it got there.
+    Rested {
+        order: Order,
+        slot: u64,
+    },
+    Traded(Trade),
+    /// An iceberg order's next slice moved to `slot` at the back of its
+    /// level.
+    Replenished {
+        order_id: OrderId,
+        slot: u64,
+    },
+    /// A resting order's total quantity was lowered, by an in-place amend
+    /// or by self-trade prevention.
+    Resized {
+        order_id: OrderId,
+        quantity: Quantity,
+    },
+    /// A stop order reached its stop price and left the stop queue.
+    Triggered(OrderId),
+    Expired(OrderId),
+    Cancelled {
+        order_id: OrderId,
+        reason: CancelReason,
+    },
+}
+
+impl<L: PriceLadder> OrderBook<L> {
+    /// Rebuilds a book created at `initial_price` from the events it has
+    /// logged since.
+    pub fn from_events<'a>(
+        initial_price: Price,
+        events: impl IntoIterator<Item = &'a Event>,
+    ) -> Self {
+        let mut book = Self::with_ladder(initial_price);
+        for event in events {
+            book.apply(event);
+        }
+        book
+    }
+
+    /// Applies the state change `event` describes and appends it to the
+    /// log. Events about orders that aren't resting, such as a taker
+    /// cancelled before it could rest, change nothing.
+    pub fn apply(&mut self, event: &Event) {
+        match event {
+            Event::Accepted(order) => {
+                self.next_order_id = self.next_order_id.max(order.id.0 + 1);
+            }
+            Event::Linked(first, second) => {
+                self.links.insert(*first, *second);
+                self.links.insert(*second, *first);
+            }
+            Event::Rested { order, slot } => {
+                let queue = match order.order_type {
+                    OrderType::Market => QueueKind::Market,
+                    OrderType::Limit => QueueKind::Limit,
+                    OrderType::Stop | OrderType::StopLimit => QueueKind::Stop,
+                };
+                self.next_slot = self.next_slot.max(slot + 1);
+                self.rest_at(queue, *slot, order.clone());
+            }
+            Event::Traded(trade) => {
+                self.last_price_level = trade.price;
+                for order_id in [trade.maker_id, trade.taker_id] {
+                    self.unlink(order_id);
+                    let Some(&location) = self.index.get(&order_id) else {
+                        continue;
+                    };
+                    let order = self.order_mut(location);
+                    order.filled_quantity += trade.quantity;
+                    if order.filled_quantity == order.quantity {
+                        self.index.remove(&order_id);
+                        self.take(location);
+                    }
+                }
+            }
+            Event::Replenished { order_id, slot } => {
+                // the slot was used even if the order has since left
+                self.next_slot = self.next_slot.max(slot + 1);
+                if let Some(location) = self.index.get_mut(order_id) {
+                    let old_location = *location;
+                    location.slot = *slot;
+                    let new_location = *location;
+                    let order = self.take(old_location);
+                    self.place(new_location, order);
+                }
+            }
+            Event::Resized { order_id, quantity } => {
+                if let Some(&location) = self.index.get(order_id) {
+                    let order = self.order_mut(location);
+                    order.quantity = *quantity;
+                    if order.filled_quantity >= order.quantity {
+                        self.index.remove(order_id);
+                        self.take(location);
+                    }
+                }
+            }
+            Event::Triggered(order_id)
+            | Event::Expired(order_id)
+            | Event::Cancelled { order_id, .. } => {
+                // a replaced order comes back under the same id and keeps
+                // its partner
+                if !matches!(
+                    event,
+                    Event::Cancelled {
+                        reason: CancelReason::Replaced,
+                        ..
+                    }
+                ) {
+                    self.unlink(*order_id);
+                }
+                if let Some(location) = self.index.remove(order_id) {
+                    self.take(location);
+                }
+            }
+        }
+        self.events.push(event.clone());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::order::{SelfTradePrevention, TimeInForce};
+    use crate::test_utils::*;
+    use alloy::primitives::U256;
+
+    #[test]
+    fn folding_the_log_rebuilds_the_book() {
+        let mut book = OrderBook::from_initial_price(Price(U256::from(100)));
+        let mut iceberg = limit_ask(U256::from(5), U256::from(101));
+        iceberg.display_quantity = Quantity(U256::from(2));
+        book.add_order(iceberg, 0).unwrap();
+        book.add_order(limit_ask(U256::from(3), U256::from(102)), 0)
+            .unwrap();
+        let (bid, _) = book
+            .add_order(limit_bid(U256::from(4), U256::from(99)), 0)
+            .unwrap();
+        let mut expiring = limit_bid(U256::from(1), U256::from(98));
+        expiring.time_in_force = TimeInForce::GoodTilDate;
+        expiring.expire_timestamp = 5;
+        book.add_order(expiring, 0).unwrap();
+        book.add_oco(
+            limit_ask(U256::from(1), U256::from(110)),
+            stop_bid(U256::from(1), U256::from(101)),
+            0,
+        )
+        .unwrap();
+
+        book.add_order(market_bid(U256::from(3)), 0).unwrap();
+        book.match_market_orders(0);
+        book.trigger_stops(0);
+        book.amend(bid, Price(U256::from(99)), Quantity(U256::from(2)), 0)
+            .unwrap();
+        let mut own = market_ask(U256::from(1));
+        own.self_trade_prevention = SelfTradePrevention::DecrementAndCancel;
+        book.add_order(own, 0).unwrap();
+        book.match_market_orders(0);
+        book.expire(10);
+
+        let folded = <OrderBook>::from_events(Price(U256::from(100)), book.events());
+        assert_eq!(folded.bids, book.bids);
+        assert_eq!(folded.asks, book.asks);
+        assert_eq!(folded.stop_bids, book.stop_bids);
+        assert_eq!(folded.market_bids, book.market_bids);
+        assert_eq!(folded.market_asks, book.market_asks);
+        assert_eq!(folded.links, book.links);
+        assert_eq!(folded.index.len(), book.index.len());
+        assert_eq!(folded.owner_orders, book.owner_orders);
+        assert_eq!(folded.last_price_level, book.last_price_level);
+        assert_eq!(folded.next_slot, book.next_slot);
+        assert_eq!(folded.events(), book.events());
+    }
+
+    #[test]
+    fn folding_matches_expired_oco_legs() {
+        let mut book = OrderBook::from_initial_price(Price(U256::from(100)));
+        let mut stop = stop_ask(U256::from(1), U256::from(95));
+        stop.time_in_force = TimeInForce::GoodTilDate;
+        stop.expire_timestamp = 5;
+        let ((bid, _), _) = book
+            .add_oco(limit_bid(U256::from(1), U256::from(90)), stop, 0)
+            .unwrap();
+        let mut taker = market_bid(U256::from(1));
+        taker.time_in_force = TimeInForce::GoodTilDate;
+        taker.expire_timestamp = 5;
+        let ((_, taker), _) = book
+            .add_oco(limit_ask(U256::from(1), U256::from(120)), taker, 0)
+            .unwrap();
+
+        // both expire lazily, when the queues they wait in are next walked
+        book.add_order(limit_ask(U256::from(1), U256::from(95)), 10)
+            .unwrap();
+        book.add_order(limit_bid(U256::from(1), U256::from(95)), 10)
+            .unwrap();
+        book.match_market_orders(10);
+        book.trigger_stops(10);
+        assert!(book.events().contains(&Event::Expired(taker)));
+        assert!(book.links.is_empty());
+        assert!(book.order(bid).is_some());
+
+        let folded = <OrderBook>::from_events(Price(U256::from(100)), book.events());
+        assert_eq!(folded.links, book.links);
+        assert_eq!(folded.bids, book.bids);
+        assert_eq!(folded.asks, book.asks);
+        assert_eq!(folded.stop_asks, book.stop_asks);
+        assert_eq!(folded.market_bids, book.market_bids);
+        assert_eq!(folded.index.len(), book.index.len());
+    }
+
+    #[test]
+    fn folding_keeps_amended_oco_legs_linked() {
+        let mut book = OrderBook::from_initial_price(Price(U256::from(100)));
+        let ((bid, stop), _) = book
+            .add_oco(
+                limit_bid(U256::from(1), U256::from(90)),
+                stop_ask(U256::from(1), U256::from(95)),
+                0,
+            )
+            .unwrap();
+        book.amend(bid, Price(U256::from(91)), Quantity(U256::from(1)), 0)
+            .unwrap();
+        assert_eq!(book.links[&bid], stop);
+
+        let folded = <OrderBook>::from_events(Price(U256::from(100)), book.events());
+        assert_eq!(folded.links, book.links);
+        assert_eq!(folded.bids, book.bids);
+        assert_eq!(folded.next_slot, book.next_slot);
+    }
+}
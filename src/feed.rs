@@ -0,0 +1,515 @@
+use std::collections::{BTreeMap, HashMap};
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use crate::book::{DepthLevel, DepthSnapshot, OrderBook, QueueKind};
+use crate::event::Event;
+use crate::order::{Order, OrderId, OrderType, Side};
+use crate::units::{Price, Quantity};
+
+/// A change to one aggregated limit price level.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum LevelUpdate {
+    Added(Side, DepthLevel),
+    /// The level's quantity or order count changed.
+    Changed(Side, DepthLevel),
+    Removed(Side, Price),
+}
+
+/// The level updates one or more book operations caused. Sequence numbers
+/// go up by one per diff, so a consumer that sees a gap has missed one and
+/// must take a fresh snapshot.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct DepthDiff {
+    /// The book's epoch; sequence numbers only compare within one.
+    pub epoch: u64,
+    pub sequence: u64,
+    pub updates: Vec<LevelUpdate>,
+}
+
+/// A change to one resting limit order. Quantities are visible
+/// quantities, so an iceberg order shows its current slice.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum OrderUpdate {
+    /// The order joined its price level at `slot`. Orders in a level
+    /// queue in slot order, so it goes ahead of any with a later slot:
+    /// usually none, but an amend under priority retention keeps the order
+    /// near its old place.
+    Add {
+        order_id: OrderId,
+        side: Side,
+        price: Price,
+        quantity: Quantity,
+        slot: u64,
+    },
+    /// The order was resized in place and keeps its queue position.
+    Modify {
+        order_id: OrderId,
+        quantity: Quantity,
+    },
+    /// The order traded `quantity` at `price` as the maker.
+    Execute {
+        order_id: OrderId,
+        price: Price,
+        quantity: Quantity,
+    },
+    /// The order left the book, filled or otherwise.
+    Delete { order_id: OrderId },
+}
+
+/// An order update with its position in the feed.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct OrderMessage {
+    /// The book's epoch; sequence numbers only compare within one.
+    pub epoch: u64,
+    /// One more than the previous message's, starting at 1.
+    pub sequence: u64,
+    pub update: OrderUpdate,
+}
+
+/// Publishes every change to a book's resting limit orders, read from its
+/// event log, so consumers can keep an exact copy of the book's visible
+/// orders in queue order. Stop and market orders aren't public and don't
+/// appear until they rest as limit orders.
+pub struct OrderFeed {
+    epoch: u64,
+    /// Sequence number of the next event to look at.
+    cursor: u64,
+    sequence: u64,
+    /// The resting limit orders as the feed last described them.
+    orders: HashMap<OrderId, Order>,
+}
+
+impl OrderFeed {
+    /// Starts a feed from `book` as it stands now. Consumers seed their
+    /// copy from the limit orders of `OrderBook::snapshot`, which are in
+    /// queue order, taken at the same time.
+    pub fn new(book: &OrderBook) -> Self {
+        let orders = book
+            .bids
+            .values()
+            .chain(book.asks.values())
+            .flat_map(|level| level.values())
+            .map(|order| (order.id, order.clone()))
+            .collect();
+        Self {
+            epoch: book.epoch(),
+            cursor: book.next_event_sequence(),
+            sequence: 0,
+            orders,
+        }
+    }
+
+    /// Epoch of the book the feed was started from.
+    pub fn epoch(&self) -> u64 {
+        self.epoch
+    }
+
+    /// Sequence number of the last message published; zero before the
+    /// first.
+    pub fn sequence(&self) -> u64 {
+        self.sequence
+    }
+
+    /// Publishes the order updates behind the events `book` logged since
+    /// the last call, in the order the book made them. Fails without
+    /// publishing anything if the book truncated events the feed hadn't
+    /// read; start a new feed from the book then.
+    pub fn poll(&mut self, book: &OrderBook) -> Result<Vec<OrderMessage>> {
+        let events = book.events_since(self.cursor)?;
+        self.cursor = book.next_event_sequence();
+        let mut updates = Vec::new();
+        for event in events {
+            match event {
+                Event::Rested { order, slot } if order.order_type == OrderType::Limit => {
+                    updates.push(Self::add(order, *slot));
+                    self.orders.insert(order.id, order.clone());
+                }
+                Event::Traded(trade) => {
+                    for order_id in [trade.maker_id, trade.taker_id] {
+                        let Some(order) = self.orders.get_mut(&order_id) else {
+                            continue;
+                        };
+                        order.filled_quantity += trade.quantity;
+                        updates.push(OrderUpdate::Execute {
+                            order_id,
+                            price: trade.price,
+                            quantity: trade.quantity,
+                        });
+                        if order.filled_quantity == order.quantity {
+                            self.orders.remove(&order_id);
+                            updates.push(OrderUpdate::Delete { order_id });
+                        }
+                    }
+                }
+                Event::Replenished { order_id, slot } => {
+                    if let Some(order) = self.orders.get(order_id) {
+                        updates.push(OrderUpdate::Delete {
+                            order_id: *order_id,
+                        });
+                        updates.push(Self::add(order, *slot));
+                    }
+                }
+                Event::Resized { order_id, quantity } => {
+                    let Some(order) = self.orders.get_mut(order_id) else {
+                        continue;
+                    };
+                    order.quantity = *quantity;
+                    if order.filled_quantity >= order.quantity {
+                        self.orders.remove(order_id);
+                        updates.push(OrderUpdate::Delete {
+                            order_id: *order_id,
+                        });
+                    } else {
+                        updates.push(OrderUpdate::Modify {
+                            order_id: *order_id,
+                            quantity: order.visible_quantity(),
+                        });
+                    }
+                }
+                Event::Expired(order_id) | Event::Cancelled { order_id, .. } => {
+                    if self.orders.remove(order_id).is_some() {
+                        updates.push(OrderUpdate::Delete {
+                            order_id: *order_id,
+                        });
+                    }
+                }
+                Event::Accepted(_)
+                | Event::Linked(..)
+                | Event::Rested { .. }
+                | Event::Triggered(_) => {}
+            }
+        }
+        let messages = updates
+            .into_iter()
+            .map(|update| {
+                self.sequence += 1;
+                OrderMessage {
+                    epoch: self.epoch,
+                    sequence: self.sequence,
+                    update,
+                }
+            })
+            .collect();
+        Ok(messages)
+    }
+
+    fn add(order: &Order, slot: u64) -> OrderUpdate {
+        OrderUpdate::Add {
+            order_id: order.id,
+            side: order.side,
+            price: order.price_bound(),
+            quantity: order.visible_quantity(),
+            slot,
+        }
+    }
+}
+
+/// Publishes incremental updates to a book's aggregated depth, read from
+/// its event log, so consumers can keep a local copy without
+/// resnapshotting.
+pub struct DepthFeed {
+    epoch: u64,
+    /// Sequence number of the next event to look at.
+    cursor: u64,
+    sequence: u64,
+    /// Side and price of each resting limit order, which later events
+    /// don't carry.
+    orders: HashMap<OrderId, (Side, Price)>,
+    /// Levels as last published.
+    bids: BTreeMap<Price, DepthLevel>,
+    asks: BTreeMap<Price, DepthLevel>,
+}
+
+impl DepthFeed {
+    /// Starts a feed from `book` as it stands now, which is what
+    /// `snapshot` shows until the first diff.
+    pub fn new(book: &OrderBook) -> Self {
+        let orders = book
+            .index
+            .iter()
+            .filter(|(_, location)| location.queue == QueueKind::Limit)
+            .map(|(order_id, location)| (*order_id, (location.side, location.price)))
+            .collect();
+        let depth = book.depth(usize::MAX);
+        let by_price = |levels: Vec<DepthLevel>| {
+            let levels = levels.into_iter().map(|level| (level.price, level));
+            levels.collect()
+        };
+        Self {
+            epoch: book.epoch(),
+            cursor: book.next_event_sequence(),
+            sequence: 0,
+            orders,
+            bids: by_price(depth.bids),
+            asks: by_price(depth.asks),
+        }
+    }
+
+    /// Epoch of the book the feed was started from.
+    pub fn epoch(&self) -> u64 {
+        self.epoch
+    }
+
+    /// Sequence number of the last diff published; zero before the first.
+    pub fn sequence(&self) -> u64 {
+        self.sequence
+    }
+
+    /// Every level as of the last diff, with that diff's sequence number.
+    /// Consumers apply the diffs numbered after it.
+    pub fn snapshot(&self) -> (u64, DepthSnapshot) {
+        let depth = DepthSnapshot {
+            bids: self.bids.values().rev().copied().collect(),
+            asks: self.asks.values().copied().collect(),
+        };
+        (self.sequence, depth)
+    }
+
+    /// Publishes the level changes behind the events `book` logged since
+    /// the last call as one diff, or `None` if no level changed. Call after
+    /// each operation on the book for a diff per operation. Fails like
+    /// `OrderFeed::poll` if the book truncated events the feed hadn't read.
+    pub fn poll(&mut self, book: &OrderBook) -> Result<Option<DepthDiff>> {
+        let events = book.events_since(self.cursor)?;
+        self.cursor = book.next_event_sequence();
+        let mut order_ids = Vec::new();
+        let mut touched = Vec::new();
+        for event in events {
+            let ids = match event {
+                Event::Rested { order, .. } => {
+                    if let (OrderType::Limit, Some(price)) = (order.order_type, order.limit_price) {
+                        self.orders.insert(order.id, (order.side, price));
+                    }
+                    [Some(order.id), None]
+                }
+                Event::Traded(trade) => [Some(trade.maker_id), Some(trade.taker_id)],
+                Event::Replenished { order_id, .. }
+                | Event::Resized { order_id, .. }
+                | Event::Triggered(order_id)
+                | Event::Expired(order_id)
+                | Event::Cancelled { order_id, .. } => [Some(*order_id), None],
+                Event::Accepted(_) | Event::Linked(..) => [None, None],
+            };
+            for order_id in ids.into_iter().flatten() {
+                if let Some(level) = self.orders.get(&order_id) {
+                    if !touched.contains(level) {
+                        touched.push(*level);
+                    }
+                    order_ids.push(order_id);
+                }
+            }
+        }
+        for order_id in order_ids {
+            if book.order(order_id).is_none() {
+                self.orders.remove(&order_id);
+            }
+        }
+
+        let mut updates = Vec::new();
+        for (side, price) in touched {
+            let published = match side {
+                Side::Bid => &mut self.bids,
+                Side::Ask => &mut self.asks,
+            };
+            let update = match (published.get(&price), book.depth_at(side, price)) {
+                (None, Some(level)) => LevelUpdate::Added(side, level),
+                (Some(old), Some(level)) if *old != level => LevelUpdate::Changed(side, level),
+                (Some(_), None) => LevelUpdate::Removed(side, price),
+                _ => continue,
+            };
+            match update {
+                LevelUpdate::Added(_, level) | LevelUpdate::Changed(_, level) => {
+                    published.insert(price, level);
+                }
+                LevelUpdate::Removed(..) => {
+                    published.remove(&price);
+                }
+            }
+            updates.push(update);
+        }
+        if updates.is_empty() {
+            return Ok(None);
+        }
+        self.sequence += 1;
+        Ok(Some(DepthDiff {
+            epoch: self.epoch,
+            sequence: self.sequence,
+            updates,
+        }))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::book::PriorityRetention;
+    use crate::test_utils::*;
+    use alloy::primitives::U256;
+
+    #[test]
+    fn diffs_keep_a_local_book_in_step() {
+        let mut book = OrderBook::from_initial_price(Price(U256::from(100)));
+        let (bid, _) = book
+            .add_order(limit_bid(U256::from(2), U256::from(99)), 0)
+            .unwrap();
+        let mut feed = DepthFeed::new(&book);
+        let (_, start) = feed.snapshot();
+        assert_eq!(start, book.depth(usize::MAX));
+
+        let (ask, _) = book
+            .add_order(limit_ask(U256::from(3), U256::from(101)), 0)
+            .unwrap();
+        let diff = feed.poll(&book).unwrap().unwrap();
+        assert_eq!(diff.sequence, 1);
+        assert!(matches!(
+            diff.updates[..],
+            [LevelUpdate::Added(Side::Ask, level)] if level.quantity == Quantity(U256::from(3))
+        ));
+
+        book.add_order(limit_bid(U256::from(1), U256::from(101)), 0)
+            .unwrap();
+        let diff = feed.poll(&book).unwrap().unwrap();
+        assert!(matches!(
+            diff.updates[..],
+            [LevelUpdate::Changed(Side::Ask, level)] if level.quantity == Quantity(U256::from(2))
+        ));
+
+        book.cancel(ask).unwrap();
+        book.amend(bid, Price(U256::from(98)), Quantity(U256::from(2)), 0)
+            .unwrap();
+        let diff = feed.poll(&book).unwrap().unwrap();
+        assert_eq!(diff.sequence, 3);
+        assert_eq!(
+            diff.updates[..2],
+            [
+                LevelUpdate::Removed(Side::Ask, Price(U256::from(101))),
+                LevelUpdate::Removed(Side::Bid, Price(U256::from(99))),
+            ]
+        );
+        assert!(feed.poll(&book).unwrap().is_none());
+        assert_eq!(feed.snapshot(), (3, book.depth(usize::MAX)));
+    }
+
+    #[test]
+    fn feeds_read_on_across_truncation_and_restores() {
+        let mut book = OrderBook::from_initial_price(Price(U256::from(100)));
+        let mut feed = DepthFeed::new(&book);
+        book.add_order(limit_bid(U256::from(2), U256::from(99)), 0)
+            .unwrap();
+        assert!(feed.poll(&book).unwrap().is_some());
+        book.truncate_events_before(book.next_event_sequence());
+
+        let mut book = OrderBook::restore(book.snapshot()).unwrap();
+        book.add_order(limit_ask(U256::from(1), U256::from(101)), 0)
+            .unwrap();
+        let diff = feed.poll(&book).unwrap().unwrap();
+        assert!(matches!(
+            diff.updates[..],
+            [LevelUpdate::Added(Side::Ask, level)] if level.price == Price(U256::from(101))
+        ));
+        assert_eq!(feed.snapshot(), (2, book.depth(usize::MAX)));
+    }
+
+    #[test]
+    fn feeds_fail_on_events_truncated_before_they_read_them() {
+        let mut book = OrderBook::from_initial_price(Price(U256::from(100)));
+        let mut depth = DepthFeed::new(&book);
+        let mut orders = OrderFeed::new(&book);
+        book.add_order(limit_bid(U256::from(2), U256::from(99)), 0)
+            .unwrap();
+        book.truncate_events_before(book.next_event_sequence());
+
+        assert!(depth.poll(&book).is_err());
+        assert!(orders.poll(&book).is_err());
+        // nothing was published, and the feeds stay failed until replaced
+        assert_eq!((depth.sequence(), orders.sequence()), (0, 0));
+        assert!(depth.poll(&book).is_err());
+        assert!(DepthFeed::new(&book).poll(&book).unwrap().is_none());
+    }
+
+    #[test]
+    fn order_feed_describes_every_resting_order_change() {
+        let mut book = OrderBook::from_initial_price(Price(U256::from(100))).with_epoch(7);
+        let mut feed = OrderFeed::new(&book);
+        let mut iceberg = limit_ask(U256::from(5), U256::from(101));
+        iceberg.display_quantity = Quantity(U256::from(2));
+        let (ask, _) = book.add_order(iceberg, 0).unwrap();
+        let (bid, _) = book
+            .add_order(limit_bid(U256::from(3), U256::from(99)), 0)
+            .unwrap();
+        book.add_order(market_bid(U256::from(2)), 0).unwrap();
+        book.match_market_orders(0);
+        book.amend(bid, Price(U256::from(99)), Quantity(U256::from(2)), 0)
+            .unwrap();
+        book.cancel(bid).unwrap();
+
+        let messages = feed.poll(&book).unwrap();
+        assert!(messages.iter().all(|message| message.epoch == 7));
+        let sequences: Vec<_> = messages.iter().map(|message| message.sequence).collect();
+        assert_eq!(sequences, (1..=7).collect::<Vec<_>>());
+        let updates: Vec<_> = messages.into_iter().map(|message| message.update).collect();
+        let add = |order_id, side, price: u64, quantity: u64, slot| OrderUpdate::Add {
+            order_id,
+            side,
+            price: Price(U256::from(price)),
+            quantity: Quantity(U256::from(quantity)),
+            slot,
+        };
+        assert_eq!(
+            updates,
+            [
+                add(ask, Side::Ask, 101, 2, 0),
+                add(bid, Side::Bid, 99, 3, 1),
+                OrderUpdate::Execute {
+                    order_id: ask,
+                    price: Price(U256::from(101)),
+                    quantity: Quantity(U256::from(2)),
+                },
+                OrderUpdate::Delete { order_id: ask },
+                add(ask, Side::Ask, 101, 2, 3),
+                OrderUpdate::Modify {
+                    order_id: bid,
+                    quantity: Quantity(U256::from(2)),
+                },
+                OrderUpdate::Delete { order_id: bid },
+            ]
+        );
+        assert!(feed.poll(&book).unwrap().is_empty());
+    }
+
+    #[test]
+    fn order_feed_places_retained_amends_ahead_of_later_orders() {
+        let mut book = OrderBook::from_initial_price(Price(U256::from(100)))
+            .with_priority_retention(PriorityRetention {
+                tick_size: Price(U256::from(1)),
+                retained_bps: 10_000,
+            });
+        book.add_order(limit_ask(U256::from(1), U256::from(100)), 0)
+            .unwrap();
+        let (improving, _) = book
+            .add_order(limit_ask(U256::from(1), U256::from(101)), 0)
+            .unwrap();
+        let (later, _) = book
+            .add_order(limit_ask(U256::from(1), U256::from(100)), 0)
+            .unwrap();
+        let mut feed = OrderFeed::new(&book);
+
+        book.amend(
+            improving,
+            Price(U256::from(100)),
+            Quantity(U256::from(1)),
+            0,
+        )
+        .unwrap();
+        let messages = feed.poll(&book).unwrap();
+        let Some(OrderUpdate::Add { slot, .. }) = messages.last().map(|message| message.update)
+        else {
+            panic!("expected the amended order to be added back");
+        };
+        let later_slot = book.index[&later].slot;
+        assert!(slot < later_slot);
+        assert_eq!(book.index[&improving].slot, slot);
+    }
+}
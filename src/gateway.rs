@@ -0,0 +1,488 @@
+use std::collections::{HashMap, HashSet};
+use std::sync::{Arc, Mutex};
+
+use anyhow::{bail, Result};
+use futures_util::{SinkExt, StreamExt};
+use serde::{Deserialize, Serialize};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio_tungstenite::tungstenite::Message;
+
+use crate::book::{DepthSnapshot, OrderBook};
+use crate::event::Event;
+use crate::feed::{DepthDiff, DepthFeed};
+use crate::matching::Trade;
+use crate::order::OrderId;
+use crate::ticker::{Ticker, TickerStats};
+
+/// A stream clients can subscribe to. `Account` is private and needs a
+/// login first.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Channel {
+    Depth,
+    Trades,
+    Ticker,
+    /// The logged-in owner's order events, fills included.
+    Account,
+}
+
+/// One message published on a channel, stamped with the epoch of the
+/// book it came from. See `OrderBook::with_epoch`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+#[serde(tag = "channel", rename_all = "snake_case")]
+pub enum Publication {
+    Depth(DepthDiff),
+    Trades {
+        epoch: u64,
+        #[serde(flatten)]
+        trade: Trade,
+    },
+    Ticker {
+        epoch: u64,
+        #[serde(flatten)]
+        ticker: Ticker,
+    },
+    /// An event about one of `owner`'s orders. A trade between two owners
+    /// is published to both.
+    Account {
+        epoch: u64,
+        owner: String,
+        event: Event,
+    },
+}
+
+impl Publication {
+    pub fn channel(&self) -> Channel {
+        match self {
+            Self::Depth(_) => Channel::Depth,
+            Self::Trades { .. } => Channel::Trades,
+            Self::Ticker { .. } => Channel::Ticker,
+            Self::Account { .. } => Channel::Account,
+        }
+    }
+}
+
+/// Turns a book's event log into channel publications. Runs on the
+/// engine's side, without any I/O.
+pub struct MarketDataPublisher {
+    epoch: u64,
+    depth: DepthFeed,
+    ticker: TickerStats,
+    /// Sequence number of the next event to look at.
+    cursor: u64,
+    /// Owner of every order still on the book, for routing its events.
+    owners: HashMap<OrderId, String>,
+}
+
+impl MarketDataPublisher {
+    /// Starts publishing from `book` as it stands now.
+    pub fn new(book: &OrderBook) -> Self {
+        let owners = book
+            .index
+            .keys()
+            .filter_map(|order_id| Some((*order_id, book.order(*order_id)?.owner.clone())))
+            .collect();
+        Self {
+            epoch: book.epoch(),
+            depth: DepthFeed::new(book),
+            ticker: TickerStats::new(TickerStats::DAY)
+                .with_first_event(book.first_event_sequence()),
+            cursor: book.next_event_sequence(),
+            owners,
+        }
+    }
+
+    /// Epoch of the book the publisher was started from.
+    pub fn epoch(&self) -> u64 {
+        self.epoch
+    }
+
+    /// The depth the diffs published so far build up to. See
+    /// `DepthFeed::snapshot`.
+    pub fn depth_snapshot(&self) -> (u64, DepthSnapshot) {
+        self.depth.snapshot()
+    }
+
+    /// Publications for the events `book` logged since the last call, with
+    /// the ticker as of `now`. Fails if the book truncated events the
+    /// publisher hadn't read; start a new one from the book then.
+    pub fn publish(&mut self, book: &OrderBook, now: u64) -> Result<Vec<Publication>> {
+        let events = book.events_since(self.cursor)?;
+        self.cursor = book.next_event_sequence();
+        let mut publications = Vec::new();
+        let mut touched = Vec::new();
+        for event in events {
+            let order_ids = match event {
+                Event::Accepted(order) => {
+                    self.owners.insert(order.id, order.owner.clone());
+                    [Some(order.id), None]
+                }
+                Event::Traded(trade) => {
+                    publications.push(Publication::Trades {
+                        epoch: self.epoch,
+                        trade: trade.clone(),
+                    });
+                    [Some(trade.maker_id), Some(trade.taker_id)]
+                }
+                Event::Linked(first, _) => [Some(*first), None],
+                Event::Rested { order, .. } => [Some(order.id), None],
+                Event::Replenished { order_id, .. }
+                | Event::Resized { order_id, .. }
+                | Event::Triggered(order_id)
+                | Event::Expired(order_id)
+                | Event::Cancelled { order_id, .. } => [Some(*order_id), None],
+            };
+            let mut owners: Vec<&String> = Vec::new();
+            for order_id in order_ids.into_iter().flatten() {
+                touched.push(order_id);
+                if let Some(owner) = self.owners.get(&order_id) {
+                    if !owners.contains(&owner) {
+                        owners.push(owner);
+                    }
+                }
+            }
+            publications.extend(owners.into_iter().map(|owner| Publication::Account {
+                epoch: self.epoch,
+                owner: owner.clone(),
+                event: event.clone(),
+            }));
+        }
+        for order_id in touched {
+            if book.order(order_id).is_none() {
+                self.owners.remove(&order_id);
+            }
+        }
+        if let Some(diff) = self.depth.poll(book)? {
+            publications.push(Publication::Depth(diff));
+        }
+        if !events.is_empty() {
+            publications.push(Publication::Ticker {
+                epoch: self.epoch,
+                ticker: self.ticker.update(book, now)?,
+            });
+        }
+        Ok(publications)
+    }
+}
+
+/// Maps a client's login token to the owner whose account channel it may
+/// read.
+pub trait Authenticator: Send + Sync {
+    fn authenticate(&self, token: &str) -> Option<String>;
+}
+
+/// A publication serialised once for every client.
+struct Frame {
+    channel: Channel,
+    owner: Option<String>,
+    json: String,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "op", rename_all = "snake_case")]
+enum Request {
+    Login { token: String },
+    Subscribe { channel: Channel },
+    Unsubscribe { channel: Channel },
+}
+
+/// WebSocket server fanning publications out to subscribed clients.
+///
+/// Clients send JSON requests: `{"op":"login","token":...}`,
+/// `{"op":"subscribe","channel":"depth"}` and `unsubscribe` likewise.
+/// Subscribing to depth first sends a `depth_snapshot` holding the epoch
+/// and the sequence number it reflects; diffs at or below it are already
+/// part of it. Publishing never waits on a client: each may fall `buffer`
+/// publications behind, and one that falls further is sent an error and
+/// disconnected, so it can reconnect and resnapshot.
+pub struct Gateway {
+    publisher: Mutex<MarketDataPublisher>,
+    sender: broadcast::Sender<Arc<Frame>>,
+    authenticator: Box<dyn Authenticator>,
+}
+
+impl Gateway {
+    pub fn new(
+        book: &OrderBook,
+        buffer: usize,
+        authenticator: impl Authenticator + 'static,
+    ) -> Result<Arc<Self>> {
+        if buffer == 0 {
+            bail!("Gateway buffer must be positive");
+        }
+        let (sender, _) = broadcast::channel(buffer);
+        Ok(Arc::new(Self {
+            publisher: Mutex::new(MarketDataPublisher::new(book)),
+            sender,
+            authenticator: Box::new(authenticator),
+        }))
+    }
+
+    /// Publishes what changed in `book` since the last call. Call from the
+    /// engine's thread after each command.
+    pub fn publish(&self, book: &OrderBook, now: u64) -> Result<()> {
+        let publications = self.publisher.lock().unwrap().publish(book, now)?;
+        for publication in publications {
+            let owner = match &publication {
+                Publication::Account { owner, .. } => Some(owner.clone()),
+                _ => None,
+            };
+            let frame = Frame {
+                channel: publication.channel(),
+                owner,
+                json: serde_json::to_string(&publication)?,
+            };
+            // no receivers just means no clients are connected
+            let _ = self.sender.send(Arc::new(frame));
+        }
+        Ok(())
+    }
+
+    /// Accepts clients on `listener` until it fails.
+    pub async fn serve(self: Arc<Self>, listener: TcpListener) -> Result<()> {
+        loop {
+            let (stream, _) = listener.accept().await?;
+            let gateway = self.clone();
+            tokio::spawn(async move {
+                let _ = gateway.session(stream).await;
+            });
+        }
+    }
+
+    async fn session(&self, stream: TcpStream) -> Result<()> {
+        let mut socket = tokio_tungstenite::accept_async(stream).await?;
+        let mut frames = self.sender.subscribe();
+        let mut channels = HashSet::new();
+        let mut owner = None;
+        loop {
+            tokio::select! {
+                message = socket.next() => {
+                    let text = match message {
+                        Some(Ok(Message::Text(text))) => text,
+                        Some(Ok(Message::Close(_))) | None => return Ok(()),
+                        Some(Ok(_)) => continue,
+                        Some(Err(err)) => return Err(err.into()),
+                    };
+                    let reply = self.handle(&text, &mut channels, &mut owner)?;
+                    if let Some(reply) = reply {
+                        socket.send(Message::text(reply)).await?;
+                    }
+                }
+                frame = frames.recv() => match frame {
+                    Ok(frame) => {
+                        let wanted = channels.contains(&frame.channel)
+                            && (frame.owner.is_none() || frame.owner == owner);
+                        if wanted {
+                            socket.send(Message::text(frame.json.clone())).await?;
+                        }
+                    }
+                    Err(RecvError::Lagged(_)) => {
+                        let error = serde_json::json!({ "error": "Client fell behind" });
+                        socket.send(Message::text(error.to_string())).await?;
+                        socket.close(None).await?;
+                        return Ok(());
+                    }
+                    Err(RecvError::Closed) => return Ok(()),
+                },
+            }
+        }
+    }
+
+    /// Applies one client request and returns the reply to send, if any.
+    fn handle(
+        &self,
+        text: &str,
+        channels: &mut HashSet<Channel>,
+        owner: &mut Option<String>,
+    ) -> Result<Option<String>> {
+        let error = |message: &str| Ok(Some(serde_json::json!({ "error": message }).to_string()));
+        let Ok(request) = serde_json::from_str(text) else {
+            return error("Malformed request");
+        };
+        match request {
+            Request::Login { token } => match self.authenticator.authenticate(&token) {
+                Some(authenticated) => *owner = Some(authenticated),
+                None => return error("Login failed"),
+            },
+            Request::Subscribe {
+                channel: Channel::Account,
+            } if owner.is_none() => return error("Log in to subscribe to the account channel"),
+            Request::Subscribe { channel } => {
+                channels.insert(channel);
+                if channel == Channel::Depth {
+                    let publisher = self.publisher.lock().unwrap();
+                    let (sequence, depth) = publisher.depth_snapshot();
+                    let snapshot = serde_json::json!({
+                        "channel": "depth_snapshot",
+                        "epoch": publisher.epoch(),
+                        "sequence": sequence,
+                        "depth": depth,
+                    });
+                    return Ok(Some(snapshot.to_string()));
+                }
+            }
+            Request::Unsubscribe { channel } => {
+                channels.remove(&channel);
+            }
+        }
+        Ok(None)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+    use crate::units::Price;
+    use alloy::primitives::U256;
+
+    #[test]
+    fn publisher_routes_events_to_their_owners() {
+        let mut book = OrderBook::from_initial_price(Price(U256::from(100))).with_epoch(7);
+        let mut publisher = MarketDataPublisher::new(&book);
+        let mut maker = limit_ask(U256::from(1), U256::from(101));
+        maker.owner = "maker".to_string();
+        book.add_order(maker, 0).unwrap();
+        book.add_order(limit_bid(U256::from(1), U256::from(101)), 0)
+            .unwrap();
+
+        let publications = publisher.publish(&book, 0).unwrap();
+        let channels: Vec<_> = publications.iter().map(Publication::channel).collect();
+        assert_eq!(
+            channels
+                .iter()
+                .filter(|channel| **channel == Channel::Trades)
+                .count(),
+            1
+        );
+        let trade_owners: Vec<_> = publications
+            .iter()
+            .filter_map(|publication| match publication {
+                Publication::Account {
+                    owner,
+                    event: Event::Traded(_),
+                    ..
+                } => Some(owner.as_str()),
+                _ => None,
+            })
+            .collect();
+        assert_eq!(trade_owners, ["maker", "owner"]);
+        assert_eq!(channels.last(), Some(&Channel::Ticker));
+        assert!(publisher.owners.is_empty());
+
+        let json = serde_json::to_value(&publications[0]).unwrap();
+        assert_eq!(json["channel"], "account");
+        assert_eq!(json["owner"], "maker");
+        assert_eq!(json["epoch"], 7);
+        let trade = publications
+            .iter()
+            .find(|publication| publication.channel() == Channel::Trades)
+            .unwrap();
+        let json = serde_json::to_value(trade).unwrap();
+        assert_eq!(json["epoch"], 7);
+        assert_eq!(json["maker_id"], 1);
+    }
+
+    struct Tokens;
+
+    impl Authenticator for Tokens {
+        fn authenticate(&self, token: &str) -> Option<String> {
+            (token == "secret").then(|| "owner".to_string())
+        }
+    }
+
+    fn reply(gateway: &Gateway, text: &str, owner: &mut Option<String>) -> serde_json::Value {
+        let reply = gateway.handle(text, &mut HashSet::new(), owner).unwrap();
+        serde_json::from_str(&reply.unwrap()).unwrap()
+    }
+
+    #[test]
+    fn gateway_rejects_a_zero_buffer() {
+        let book = OrderBook::from_initial_price(Price(U256::from(100)));
+        let err = Gateway::new(&book, 0, Tokens).err().unwrap();
+        assert_eq!(err.to_string(), "Gateway buffer must be positive");
+    }
+
+    #[test]
+    fn account_channel_needs_a_login() {
+        let book = OrderBook::from_initial_price(Price(U256::from(100)));
+        let gateway = Gateway::new(&book, 4, Tokens).unwrap();
+        let mut owner = None;
+        let subscribe = r#"{"op":"subscribe","channel":"account"}"#;
+        let refused = reply(&gateway, subscribe, &mut owner);
+        assert_eq!(
+            refused["error"],
+            "Log in to subscribe to the account channel"
+        );
+        let failed = reply(&gateway, r#"{"op":"login","token":"wrong"}"#, &mut owner);
+        assert_eq!(failed["error"], "Login failed");
+        assert_eq!(
+            reply(&gateway, "{", &mut owner)["error"],
+            "Malformed request"
+        );
+
+        let mut channels = HashSet::new();
+        let login = r#"{"op":"login","token":"secret"}"#;
+        assert!(gateway
+            .handle(login, &mut channels, &mut owner)
+            .unwrap()
+            .is_none());
+        assert!(gateway
+            .handle(subscribe, &mut channels, &mut owner)
+            .unwrap()
+            .is_none());
+        assert!(channels.contains(&Channel::Account));
+    }
+
+    #[test]
+    fn depth_subscription_starts_with_a_sequenced_snapshot() {
+        let mut book = OrderBook::from_initial_price(Price(U256::from(100))).with_epoch(3);
+        let gateway = Gateway::new(&book, 4, Tokens).unwrap();
+        book.add_order(limit_bid(U256::from(2), U256::from(99)), 0)
+            .unwrap();
+        gateway.publish(&book, 0).unwrap();
+
+        let snapshot = reply(
+            &gateway,
+            r#"{"op":"subscribe","channel":"depth"}"#,
+            &mut None,
+        );
+        assert_eq!(snapshot["channel"], "depth_snapshot");
+        assert_eq!(snapshot["epoch"], 3);
+        assert_eq!(snapshot["sequence"], 1);
+        assert_eq!(snapshot["depth"]["bids"].as_array().unwrap().len(), 1);
+    }
+
+    #[tokio::test]
+    async fn lagging_clients_are_disconnected() {
+        let mut book = OrderBook::from_initial_price(Price(U256::from(100)));
+        let gateway = Gateway::new(&book, 1, Tokens).unwrap();
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let server = tokio::spawn(gateway.clone().serve(listener));
+        let (mut client, _) = tokio_tungstenite::connect_async(format!("ws://{address}"))
+            .await
+            .unwrap();
+        let subscribe = r#"{"op":"subscribe","channel":"depth"}"#;
+        client.send(Message::text(subscribe)).await.unwrap();
+        // the snapshot reply means the session is reading publications
+        assert!(client.next().await.unwrap().unwrap().is_text());
+
+        book.add_order(limit_ask(U256::from(1), U256::from(101)), 0)
+            .unwrap();
+        book.add_order(limit_bid(U256::from(1), U256::from(101)), 0)
+            .unwrap();
+        gateway.publish(&book, 0).unwrap();
+
+        let Message::Text(error) = client.next().await.unwrap().unwrap() else {
+            panic!("expected the lag error");
+        };
+        assert_eq!(error, r#"{"error":"Client fell behind"}"#);
+        assert!(matches!(
+            client.next().await,
+            Some(Ok(Message::Close(_))) | None
+        ));
+        server.abort();
+    }
+}
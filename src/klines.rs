@@ -0,0 +1,230 @@
+use std::collections::BTreeMap;
+
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use crate::book::OrderBook;
+use crate::event::Event;
+use crate::matching::Trade;
+use crate::units::{Price, Quantity};
+
+/// Length of a candle.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Interval {
+    OneMinute,
+    FiveMinutes,
+    OneHour,
+    OneDay,
+}
+
+impl Interval {
+    pub const ALL: [Interval; 4] = [
+        Interval::OneMinute,
+        Interval::FiveMinutes,
+        Interval::OneHour,
+        Interval::OneDay,
+    ];
+
+    pub fn seconds(self) -> u64 {
+        match self {
+            Self::OneMinute => 60,
+            Self::FiveMinutes => 5 * 60,
+            Self::OneHour => 60 * 60,
+            Self::OneDay => 24 * 60 * 60,
+        }
+    }
+}
+
+/// Trading activity within one interval.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Candle {
+    /// Unix timestamp the interval starts at, a multiple of its length.
+    pub open_time: u64,
+    pub open: Price,
+    pub high: Price,
+    pub low: Price,
+    pub close: Price,
+    /// Traded quantity, saturating at the top of the range.
+    pub volume: Quantity,
+    pub trade_count: u64,
+}
+
+impl Candle {
+    fn new(open_time: u64, trade: &Trade) -> Self {
+        let price = trade.price;
+        Self {
+            open_time,
+            open: price,
+            high: price,
+            low: price,
+            close: price,
+            volume: trade.quantity,
+            trade_count: 1,
+        }
+    }
+
+    fn add(&mut self, trade: &Trade) {
+        let price = trade.price;
+        self.high = self.high.max(price);
+        self.low = self.low.min(price);
+        self.close = price;
+        self.volume = self.volume.saturating_add(trade.quantity);
+        self.trade_count += 1;
+    }
+}
+
+/// Rolling OHLCV candles for every `Interval`, built from a book's trades.
+pub struct Klines {
+    /// Candles kept per interval; older ones are dropped.
+    retain: usize,
+    /// Sequence number of the next event to look at.
+    cursor: u64,
+    /// Candles by open time, one map per entry of `Interval::ALL`.
+    candles: [BTreeMap<u64, Candle>; 4],
+}
+
+impl Klines {
+    /// Keeps the latest `retain` candles of each interval.
+    pub fn new(retain: usize) -> Self {
+        Self {
+            retain,
+            cursor: 0,
+            candles: Default::default(),
+        }
+    }
+
+    /// Reads the book's events from `sequence` on rather than from its
+    /// creation. See `TickerStats::with_first_event`.
+    pub fn with_first_event(mut self, sequence: u64) -> Self {
+        self.cursor = sequence;
+        self
+    }
+
+    /// Adds the trades `book` logged since the last call. Fails without
+    /// adding any if the book truncated events not yet read, since trades
+    /// among them would be missing from the candles.
+    pub fn update(&mut self, book: &OrderBook) -> Result<()> {
+        let events = book.events_since(self.cursor)?;
+        self.cursor = book.next_event_sequence();
+        for event in events {
+            if let Event::Traded(trade) = event {
+                self.record(trade);
+            }
+        }
+        Ok(())
+    }
+
+    /// Adds one trade to the candle covering its timestamp in each
+    /// interval. A trade older than every retained candle is dropped.
+    pub fn record(&mut self, trade: &Trade) {
+        for (interval, candles) in Interval::ALL.into_iter().zip(&mut self.candles) {
+            let open_time = trade.timestamp - trade.timestamp % interval.seconds();
+            if let Some(candle) = candles.get_mut(&open_time) {
+                candle.add(trade);
+                continue;
+            }
+            let full = candles.len() >= self.retain;
+            if full
+                && candles
+                    .first_key_value()
+                    .is_none_or(|(first, _)| open_time < *first)
+            {
+                continue;
+            }
+            candles.insert(open_time, Candle::new(open_time, trade));
+            while candles.len() > self.retain {
+                candles.pop_first();
+            }
+        }
+    }
+
+    /// Candles of `interval` opening in `from..to`, oldest first. Intervals
+    /// without trades have no candle.
+    pub fn range(&self, interval: Interval, from: u64, to: u64) -> Vec<Candle> {
+        if from >= to {
+            return Vec::new();
+        }
+        let candles = self.candles(interval);
+        candles.range(from..to).map(|(_, candle)| *candle).collect()
+    }
+
+    /// The candle of `interval` covering the latest trade.
+    pub fn latest(&self, interval: Interval) -> Option<Candle> {
+        let candles = self.candles(interval);
+        candles.last_key_value().map(|(_, candle)| *candle)
+    }
+
+    fn candles(&self, interval: Interval) -> &BTreeMap<u64, Candle> {
+        let position = Interval::ALL.iter().position(|other| *other == interval);
+        &self.candles[position.unwrap()]
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::order::{OrderId, Side};
+    use alloy::primitives::U256;
+
+    fn trade(timestamp: u64, price: u64, quantity: u64) -> Trade {
+        Trade {
+            maker_id: OrderId(1),
+            taker_id: OrderId(2),
+            price: Price(U256::from(price)),
+            quantity: Quantity(U256::from(quantity)),
+            timestamp,
+            aggressor_side: Side::Bid,
+        }
+    }
+
+    #[test]
+    fn candles_roll_up_trades_per_interval() {
+        let mut klines = Klines::new(2);
+        for (timestamp, price) in [(0, 100), (30, 105), (59, 98), (60, 101), (150, 103)] {
+            klines.record(&trade(timestamp, price, 2));
+        }
+
+        let minutes = klines.range(Interval::OneMinute, 0, u64::MAX);
+        assert_eq!(
+            minutes
+                .iter()
+                .map(|candle| candle.open_time)
+                .collect::<Vec<_>>(),
+            [60, 120]
+        );
+        let five = klines.latest(Interval::FiveMinutes).unwrap();
+        assert_eq!(five.open, Price(U256::from(100)));
+        assert_eq!(five.high, Price(U256::from(105)));
+        assert_eq!(five.low, Price(U256::from(98)));
+        assert_eq!(five.close, Price(U256::from(103)));
+        assert_eq!(five.volume, Quantity(U256::from(10)));
+        assert_eq!(five.trade_count, 5);
+
+        klines.record(&trade(0, 200, 1));
+        assert_eq!(klines.range(Interval::OneMinute, 0, 60), []);
+        assert_eq!(klines.range(Interval::OneMinute, 60, 120).len(), 1);
+    }
+
+    #[test]
+    fn candles_at_the_edges_of_their_ranges() {
+        let mut klines = Klines::new(0);
+        klines.record(&trade(0, 100, 1));
+        assert_eq!(klines.latest(Interval::OneMinute), None);
+
+        let mut klines = Klines::new(1);
+        assert_eq!(klines.latest(Interval::OneDay), None);
+        let mut largest = trade(u64::MAX, 100, 1);
+        klines.record(&largest);
+        largest.quantity = Quantity::MAX;
+        klines.record(&largest);
+        let candle = klines.latest(Interval::OneMinute).unwrap();
+        assert_eq!(candle.open_time, u64::MAX - u64::MAX % 60);
+        assert_eq!(candle.volume, Quantity::MAX);
+        assert_eq!(candle.trade_count, 2);
+
+        assert_eq!(klines.range(Interval::OneMinute, u64::MAX, 0), []);
+        assert_eq!(klines.range(Interval::OneMinute, 60, 60), []);
+        assert_eq!(klines.range(Interval::OneMinute, 0, candle.open_time), []);
+        assert_eq!(klines.range(Interval::OneMinute, 0, u64::MAX), [candle]);
+    }
+}
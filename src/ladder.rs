@@ -0,0 +1,242 @@
+use std::collections::BTreeMap;
+use std::ops::{Bound, RangeBounds};
+
+use crate::book::PriceLevel;
+use crate::units::Price;
+
+/// Price levels of one side of a book, ordered by price. Iteration runs
+/// from the lowest price up; matching walks asks forwards and bids
+/// backwards.
+pub trait PriceLadder: Default {
+    fn len(&self) -> usize;
+
+    fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+
+    fn get(&self, price: &Price) -> Option<&PriceLevel>;
+
+    fn get_mut(&mut self, price: &Price) -> Option<&mut PriceLevel>;
+
+    fn contains_key(&self, price: &Price) -> bool {
+        self.get(price).is_some()
+    }
+
+    /// The level at `price`, added empty if there was none.
+    fn level_mut(&mut self, price: Price) -> &mut PriceLevel;
+
+    fn remove(&mut self, price: &Price) -> Option<PriceLevel>;
+
+    /// Keeps only the levels `keep` returns true for.
+    fn retain(&mut self, keep: impl FnMut(&Price, &mut PriceLevel) -> bool);
+
+    fn iter(&self) -> impl DoubleEndedIterator<Item = (&Price, &PriceLevel)>;
+
+    fn range(
+        &self,
+        prices: impl RangeBounds<Price>,
+    ) -> impl DoubleEndedIterator<Item = (&Price, &PriceLevel)>;
+
+    fn range_mut(
+        &mut self,
+        prices: impl RangeBounds<Price>,
+    ) -> impl DoubleEndedIterator<Item = (&Price, &mut PriceLevel)>;
+
+    fn keys(&self) -> impl DoubleEndedIterator<Item = &Price> {
+        self.iter().map(|(price, _)| price)
+    }
+
+    fn values(&self) -> impl DoubleEndedIterator<Item = &PriceLevel> {
+        self.iter().map(|(_, level)| level)
+    }
+
+    fn values_mut(&mut self) -> impl DoubleEndedIterator<Item = &mut PriceLevel> {
+        self.range_mut(..).map(|(_, level)| level)
+    }
+
+    /// Releases capacity left over from levels that have since emptied.
+    fn shrink_to_fit(&mut self) {}
+}
+
+impl PriceLadder for BTreeMap<Price, PriceLevel> {
+    fn len(&self) -> usize {
+        BTreeMap::len(self)
+    }
+
+    fn get(&self, price: &Price) -> Option<&PriceLevel> {
+        BTreeMap::get(self, price)
+    }
+
+    fn get_mut(&mut self, price: &Price) -> Option<&mut PriceLevel> {
+        BTreeMap::get_mut(self, price)
+    }
+
+    fn level_mut(&mut self, price: Price) -> &mut PriceLevel {
+        self.entry(price).or_default()
+    }
+
+    fn remove(&mut self, price: &Price) -> Option<PriceLevel> {
+        BTreeMap::remove(self, price)
+    }
+
+    fn retain(&mut self, keep: impl FnMut(&Price, &mut PriceLevel) -> bool) {
+        BTreeMap::retain(self, keep)
+    }
+
+    fn iter(&self) -> impl DoubleEndedIterator<Item = (&Price, &PriceLevel)> {
+        BTreeMap::iter(self)
+    }
+
+    fn range(
+        &self,
+        prices: impl RangeBounds<Price>,
+    ) -> impl DoubleEndedIterator<Item = (&Price, &PriceLevel)> {
+        BTreeMap::range(self, prices)
+    }
+
+    fn range_mut(
+        &mut self,
+        prices: impl RangeBounds<Price>,
+    ) -> impl DoubleEndedIterator<Item = (&Price, &mut PriceLevel)> {
+        BTreeMap::range_mut(self, prices)
+    }
+}
+
+/// Levels kept in one vector sorted by price and found by binary search.
+/// Walking the ladder reads contiguous memory instead of chasing tree
+/// nodes, at the cost of shifting the levels above a price whenever one
+/// is added or removed there. That suits dense tick markets, where a side
+/// has a few hundred active levels and most of the churn is near the top.
+#[derive(Clone, Debug, Default)]
+pub struct SortedLadder {
+    levels: Vec<(Price, PriceLevel)>,
+}
+
+impl SortedLadder {
+    fn search(&self, price: &Price) -> Result<usize, usize> {
+        self.levels.binary_search_by(|(level, _)| level.cmp(price))
+    }
+
+    /// Positions of the levels priced within `prices`.
+    fn span(&self, prices: impl RangeBounds<Price>) -> std::ops::Range<usize> {
+        let start = match prices.start_bound() {
+            Bound::Included(price) => self.levels.partition_point(|(level, _)| level < price),
+            Bound::Excluded(price) => self.levels.partition_point(|(level, _)| level <= price),
+            Bound::Unbounded => 0,
+        };
+        let end = match prices.end_bound() {
+            Bound::Included(price) => self.levels.partition_point(|(level, _)| level <= price),
+            Bound::Excluded(price) => self.levels.partition_point(|(level, _)| level < price),
+            Bound::Unbounded => self.levels.len(),
+        };
+        start..end.max(start)
+    }
+}
+
+impl PriceLadder for SortedLadder {
+    fn len(&self) -> usize {
+        self.levels.len()
+    }
+
+    fn get(&self, price: &Price) -> Option<&PriceLevel> {
+        let index = self.search(price).ok()?;
+        Some(&self.levels[index].1)
+    }
+
+    fn get_mut(&mut self, price: &Price) -> Option<&mut PriceLevel> {
+        let index = self.search(price).ok()?;
+        Some(&mut self.levels[index].1)
+    }
+
+    fn level_mut(&mut self, price: Price) -> &mut PriceLevel {
+        let index = self.search(&price).unwrap_or_else(|index| {
+            self.levels.insert(index, (price, PriceLevel::new()));
+            index
+        });
+        &mut self.levels[index].1
+    }
+
+    fn remove(&mut self, price: &Price) -> Option<PriceLevel> {
+        let index = self.search(price).ok()?;
+        Some(self.levels.remove(index).1)
+    }
+
+    fn retain(&mut self, mut keep: impl FnMut(&Price, &mut PriceLevel) -> bool) {
+        self.levels.retain_mut(|(price, level)| keep(price, level));
+    }
+
+    fn iter(&self) -> impl DoubleEndedIterator<Item = (&Price, &PriceLevel)> {
+        self.levels.iter().map(|(price, level)| (price, level))
+    }
+
+    fn range(
+        &self,
+        prices: impl RangeBounds<Price>,
+    ) -> impl DoubleEndedIterator<Item = (&Price, &PriceLevel)> {
+        let span = self.span(prices);
+        self.levels[span]
+            .iter()
+            .map(|(price, level)| (price, level))
+    }
+
+    fn range_mut(
+        &mut self,
+        prices: impl RangeBounds<Price>,
+    ) -> impl DoubleEndedIterator<Item = (&Price, &mut PriceLevel)> {
+        let span = self.span(prices);
+        self.levels[span]
+            .iter_mut()
+            .map(|(price, level)| (&*price, level))
+    }
+
+    fn shrink_to_fit(&mut self) {
+        self.levels.shrink_to_fit();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+    use alloy::primitives::U256;
+
+    fn ladder<L: PriceLadder>(prices: [u64; 5]) -> L {
+        let mut ladder = L::default();
+        for price in prices {
+            let order = limit_ask(U256::from(1), U256::from(price));
+            ladder
+                .level_mut(Price(U256::from(price)))
+                .insert(price, order);
+        }
+        ladder.remove(&Price(U256::from(103)));
+        ladder
+    }
+
+    #[test]
+    fn sorted_ladder_ranges_like_a_btree_map() {
+        let prices = [105, 101, 103, 99, 107];
+        let sorted: SortedLadder = ladder(prices);
+        let tree: BTreeMap<Price, PriceLevel> = ladder(prices);
+        let price = |price: u64| Price(U256::from(price));
+        let bounds = [
+            (Bound::Included(price(101)), Bound::Included(price(105))),
+            (Bound::Excluded(price(101)), Bound::Excluded(price(107))),
+            (Bound::Unbounded, Bound::Included(price(100))),
+            (Bound::Included(price(106)), Bound::Unbounded),
+            (Bound::Included(price(102)), Bound::Excluded(price(103))),
+        ];
+        for range in bounds {
+            let walk = |levels: Vec<(&Price, &PriceLevel)>| {
+                let prices = levels.iter().map(|(price, level)| (**price, level.len()));
+                prices.collect::<Vec<_>>()
+            };
+            assert_eq!(
+                walk(sorted.range(range).rev().collect()),
+                walk(PriceLadder::range(&tree, range).rev().collect())
+            );
+        }
+        assert_eq!(sorted.len(), 4);
+        assert!(!sorted.contains_key(&price(103)));
+        assert!(sorted.keys().copied().eq(tree.keys().copied()));
+    }
+}
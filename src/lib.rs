@@ -0,0 +1,42 @@
+pub mod book;
+pub mod clock;
+pub mod engine;
+pub mod event;
+pub mod feed;
+#[cfg(feature = "ws")]
+pub mod gateway;
+pub mod klines;
+pub mod ladder;
+pub mod market;
+pub mod matching;
+pub mod order;
+pub mod replay;
+pub mod risk;
+pub mod telemetry;
+pub mod ticker;
+pub mod units;
+pub mod wal;
+
+#[cfg(test)]
+mod test_utils;
+
+pub use book::{
+    BookSnapshot, CancelFilter, ConcentrationLimit, DepthLevel, DepthSnapshot, OrderBook,
+    OrderError, PriorityRetention,
+};
+pub use engine::{Command, CommandResult, Engine, EngineSnapshot, PreTradeFilter};
+pub use event::{CancelReason, Event};
+pub use feed::{DepthDiff, DepthFeed, LevelUpdate, OrderFeed, OrderMessage, OrderUpdate};
+#[cfg(feature = "ws")]
+pub use gateway::{Authenticator, Channel, Gateway, MarketDataPublisher, Publication};
+pub use klines::{Candle, Interval, Klines};
+pub use ladder::{PriceLadder, SortedLadder};
+pub use market::MarketState;
+pub use matching::{Execution, Trade};
+pub use order::{Order, OrderBuilder, OrderId, OrderType, SelfTradePrevention, Side, TimeInForce};
+pub use replay::ReplayOutcome;
+pub use risk::{DailyLossLimit, DuplicateGuard};
+pub use telemetry::{EventSampler, TelemetryRow, TelemetrySink};
+pub use ticker::{Ticker, TickerStats};
+pub use units::{Notional, Price, Quantity};
+pub use wal::{WalConfig, WalEntry, WriteAheadLog};
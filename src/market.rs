@@ -0,0 +1,144 @@
+use serde::{Deserialize, Serialize};
+
+use crate::engine::Command;
+use crate::order::{Order, OrderType};
+
+/// Trading phase of a market, which decides the commands the engine
+/// accepts. There is no auction uncrossing yet, so phases that would
+/// collect orders for one only take orders that can't trade.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum MarketState {
+    /// Before the open: cancels and post-only limit orders.
+    PreOpen,
+    /// Call period ahead of an uncross: cancels only.
+    Auction,
+    /// Normal trading: every command.
+    #[default]
+    Continuous,
+    /// Trading suspended: cancels only.
+    Halted,
+    /// Makers only: post-only limit orders, amends that don't cross, and
+    /// cancels.
+    PostOnlySession,
+    /// After the close: cancels only.
+    Closed,
+    /// Permanently removed: cancels only, so remaining orders can be
+    /// pulled.
+    Delisted,
+}
+
+impl MarketState {
+    /// Whether `command` may run in this state. Amends allowed here must
+    /// still not cross the spread in a post-only session, which the engine
+    /// checks against the book.
+    pub fn allows(self, command: &Command) -> bool {
+        let post_only = |order: &Order| order.post_only && order.order_type == OrderType::Limit;
+        match command {
+            Command::Cancel(_) | Command::CancelAll(_) | Command::Expire => true,
+            Command::Transition(next) => self.can_transition_to(*next),
+            Command::Submit(order) => match self {
+                Self::Continuous => true,
+                Self::PreOpen | Self::PostOnlySession => post_only(order),
+                _ => false,
+            },
+            Command::SubmitDependent(orders) => match self {
+                Self::Continuous => true,
+                Self::PreOpen | Self::PostOnlySession => post_only(&orders.0),
+                _ => false,
+            },
+            Command::SubmitOco(legs) => match self {
+                Self::Continuous => true,
+                Self::PreOpen | Self::PostOnlySession => post_only(&legs.0) && post_only(&legs.1),
+                _ => false,
+            },
+            Command::Amend { .. } => matches!(self, Self::Continuous | Self::PostOnlySession),
+        }
+    }
+
+    /// Whether the market may move from this state to `next`.
+    pub fn can_transition_to(self, next: MarketState) -> bool {
+        use MarketState::*;
+        match self {
+            PreOpen => matches!(
+                next,
+                Auction | Continuous | PostOnlySession | Halted | Closed
+            ),
+            Auction => matches!(next, Continuous | Halted | Closed),
+            Continuous => matches!(next, Auction | Halted | PostOnlySession | Closed),
+            PostOnlySession => matches!(next, Auction | Continuous | Halted | Closed),
+            Halted => next != Halted,
+            Closed => matches!(next, PreOpen | Delisted),
+            Delisted => false,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::book::OrderBook;
+    use crate::clock::SystemClock;
+    use crate::engine::{Engine, EngineSnapshot};
+    use crate::test_utils::*;
+    use crate::units::{Price, Quantity};
+    use alloy::primitives::U256;
+
+    #[test]
+    fn market_state_gates_commands() {
+        let mut engine = Engine::from_initial_price(Price(U256::from(100)));
+        let (ask, _) = engine
+            .submit(limit_ask(U256::from(1), U256::from(101)))
+            .unwrap();
+        engine.transition(MarketState::PostOnlySession).unwrap();
+
+        let err = engine
+            .submit(limit_bid(U256::from(1), U256::from(100)))
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Market is PostOnlySession and does not accept this command"
+        );
+        let mut maker = limit_bid(U256::from(1), U256::from(100));
+        maker.post_only = true;
+        let (bid, _) = engine.submit(maker).unwrap();
+        let err = engine
+            .amend(bid, Price(U256::from(101)), Quantity(U256::from(1)))
+            .unwrap_err();
+        assert_eq!(err.to_string(), "Amend would take liquidity");
+
+        engine.transition(MarketState::Halted).unwrap();
+        assert!(engine.submit(market_bid(U256::from(1))).is_err());
+        assert!(engine.cancel(ask).is_ok());
+        engine.transition(MarketState::Delisted).unwrap();
+        let err = engine.transition(MarketState::Continuous).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Market cannot move from Delisted to Continuous"
+        );
+        assert_eq!(engine.state(), MarketState::Delisted);
+    }
+
+    #[test]
+    fn market_state_survives_a_restore() {
+        let mut engine = Engine::from_initial_price(Price(U256::from(100)));
+        let (ask, _) = engine
+            .submit(limit_ask(U256::from(1), U256::from(101)))
+            .unwrap();
+        engine.transition(MarketState::Halted).unwrap();
+        let json = serde_json::to_string(&engine.snapshot()).unwrap();
+
+        let snapshot: EngineSnapshot = serde_json::from_str(&json).unwrap();
+        let book = OrderBook::restore(snapshot.book.clone()).unwrap();
+        let mut restored = Engine::restore(book, snapshot, SystemClock::new()).unwrap();
+        assert_eq!(restored.state(), MarketState::Halted);
+        assert_eq!(restored.state_hash(), engine.state_hash());
+        let err = restored
+            .submit(limit_bid(U256::from(1), U256::from(99)))
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Market is Halted and does not accept this command"
+        );
+        assert!(restored.cancel(ask).is_ok());
+    }
+}
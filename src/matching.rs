@@ -0,0 +1,1176 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use alloy::primitives::U256;
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::book::{OrderBook, PriceLevel};
+use crate::event::{CancelReason, Event};
+use crate::ladder::PriceLadder;
+use crate::order::{Order, OrderId, OrderType, SelfTradePrevention, Side};
+use crate::units::{Notional, Price, Quantity};
+
+/// A single fill between a resting maker and the order that took its
+/// liquidity.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Trade {
+    pub maker_id: OrderId,
+    pub taker_id: OrderId,
+    /// The maker's price level.
+    pub price: Price,
+    pub quantity: Quantity,
+    pub timestamp: u64,
+    pub aggressor_side: Side,
+}
+
+impl Trade {
+    /// Value of the trade for a market whose prices carry `price_decimals`
+    /// decimal places; `None` on overflow.
+    pub fn notional(&self, price_decimals: u8) -> Option<Notional> {
+        self.price.notional(self.quantity, price_decimals)
+    }
+}
+
+/// The outcome of one taker matching against the book. `taker` has its
+/// fill applied, and `makers[i]` is the maker of `trades[i]` as it stood
+/// after that trade, so an iceberg maker appears once per slice it filled.
+#[derive(Clone, Debug)]
+pub struct Execution {
+    pub taker: Order,
+    pub makers: Vec<Order>,
+    pub trades: Vec<Trade>,
+}
+
+/// Running counters describing how takers interact with the book.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MatchingStats {
+    pub taker_matches: u64,
+    pub levels_swept: u64,
+    pub requested_quantity: Quantity,
+    pub executed_quantity: Quantity,
+    /// Trades against resting makers, and the seconds those makers had
+    /// rested when they traded, summed.
+    pub maker_fills: u64,
+    pub maker_resting_time: u64,
+}
+
+impl MatchingStats {
+    pub(crate) fn record_match(
+        &mut self,
+        requested: Quantity,
+        executed: Quantity,
+        levels_swept: u64,
+    ) {
+        self.taker_matches += 1;
+        self.levels_swept += levels_swept;
+        self.requested_quantity = self.requested_quantity.saturating_add(requested);
+        self.executed_quantity = self.executed_quantity.saturating_add(executed);
+    }
+
+    pub(crate) fn record_maker_fill(&mut self, rested_at: u64, now: u64) {
+        self.maker_fills += 1;
+        self.maker_resting_time = self
+            .maker_resting_time
+            .saturating_add(now.saturating_sub(rested_at));
+    }
+
+    /// Share of the quantity takers asked for that was actually executed.
+    pub fn fill_ratio(&self) -> f64 {
+        if self.requested_quantity == Quantity::ZERO {
+            return 0.0;
+        }
+        f64::from(self.executed_quantity.0) / f64::from(self.requested_quantity.0)
+    }
+
+    /// Average number of price levels a taker consumed liquidity from.
+    pub fn average_sweep_depth(&self) -> f64 {
+        if self.taker_matches == 0 {
+            return 0.0;
+        }
+        self.levels_swept as f64 / self.taker_matches as f64
+    }
+
+    /// Average seconds a maker had been resting when it traded. An iceberg
+    /// counts from when it arrived, not from its latest slice.
+    pub fn average_maker_resting_time(&self) -> f64 {
+        if self.maker_fills == 0 {
+            return 0.0;
+        }
+        self.maker_resting_time as f64 / self.maker_fills as f64
+    }
+}
+
+/// A single maker fill the book would produce for a previewed order.
+#[derive(Clone, Debug)]
+pub struct PreviewFill {
+    pub maker_owner: String,
+    pub maker_nonce: U256,
+    pub price: Price,
+    pub quantity: Quantity,
+}
+
+/// Outcome of matching an order against the book without mutating it.
+#[derive(Clone, Debug)]
+pub struct OrderPreview {
+    pub fills: Vec<PreviewFill>,
+    pub filled_quantity: Quantity,
+    pub unfilled_quantity: Quantity,
+    pub average_price: Option<Price>,
+}
+
+/// The order taking liquidity in a sweep, as far as the fill rules need it.
+#[derive(Clone, Copy)]
+pub(crate) struct Taker<'a> {
+    pub(crate) id: OrderId,
+    pub(crate) side: Side,
+    pub(crate) owner: &'a str,
+    pub(crate) limit_price: Price,
+    pub(crate) self_trade_prevention: SelfTradePrevention,
+}
+
+impl<'a> Taker<'a> {
+    pub(crate) fn of(order: &'a Order) -> Self {
+        Self {
+            id: order.id,
+            side: order.side,
+            owner: &order.owner,
+            limit_price: order.price_bound(),
+            self_trade_prevention: order.self_trade_prevention,
+        }
+    }
+
+    /// The policy to apply if trading against `maker` would be a
+    /// self-trade.
+    fn self_trade_with(&self, maker: &Order) -> Option<SelfTradePrevention> {
+        (self.self_trade_prevention != SelfTradePrevention::Allow && maker.owner == self.owner)
+            .then_some(self.self_trade_prevention)
+    }
+}
+
+/// Walks `levels` in priority order the same way the matcher does,
+/// including iceberg slices rejoining the back of their level,
+/// one-cancels-other partners dropping out and self-trade prevention, and
+/// returns the fills `quantity` would take at `now`, leaving the book
+/// untouched.
+fn simulate_sweep<'a>(
+    levels: impl Iterator<Item = (&'a Price, &'a PriceLevel)>,
+    taker: Taker<'_>,
+    quantity: Quantity,
+    now: u64,
+    links: &HashMap<OrderId, OrderId>,
+) -> Vec<PreviewFill> {
+    let mut remaining = quantity;
+    let mut fills = Vec::new();
+    // a one-cancels-other leg never trades against its own partner
+    let mut cancelled_links: HashSet<OrderId> = links.get(&taker.id).copied().into_iter().collect();
+    for (price_level, orders) in levels {
+        let mut queue: VecDeque<(&Order, Quantity)> = orders
+            .values()
+            .filter(|order| !order.is_expired(now))
+            .map(|order| (order, order.filled_quantity))
+            .collect();
+        while let Some((order, filled_quantity)) = queue.pop_front() {
+            if cancelled_links.contains(&order.id) {
+                continue;
+            }
+            match taker.self_trade_with(order) {
+                None => {}
+                Some(SelfTradePrevention::CancelOldest) => continue,
+                Some(SelfTradePrevention::DecrementAndCancel) => {
+                    remaining -= (order.quantity - filled_quantity).min(remaining);
+                    if remaining == Quantity::ZERO {
+                        return fills;
+                    }
+                    continue;
+                }
+                Some(_) => return fills,
+            }
+            let available = order.visible_at(filled_quantity);
+            let fill_quantity = if available > remaining {
+                if order.only_full_fill {
+                    continue;
+                }
+                remaining
+            } else {
+                available
+            };
+            cancelled_links.extend(links.get(&order.id));
+            fills.push(PreviewFill {
+                maker_owner: order.owner.clone(),
+                maker_nonce: order.nonce,
+                price: *price_level,
+                quantity: fill_quantity,
+            });
+            remaining -= fill_quantity;
+            if remaining == Quantity::ZERO {
+                return fills;
+            }
+            if filled_quantity + fill_quantity < order.quantity {
+                queue.push_back((order, filled_quantity + fill_quantity));
+            }
+        }
+    }
+    fills
+}
+
+fn total_quantity(fills: &[PreviewFill]) -> Quantity {
+    fills.iter().map(|fill| fill.quantity).sum()
+}
+
+impl OrderPreview {
+    fn from_fills(fills: Vec<PreviewFill>, quantity: Quantity) -> Result<Self> {
+        let mut filled_quantity = Quantity::ZERO;
+        let mut notional = U256::ZERO;
+        for fill in &fills {
+            filled_quantity += fill.quantity;
+            let Some(total) = fill
+                .price
+                .0
+                .checked_mul(fill.quantity.0)
+                .and_then(|value| notional.checked_add(value))
+            else {
+                bail!("Notional overflow");
+            };
+            notional = total;
+        }
+        let average_price =
+            (filled_quantity > Quantity::ZERO).then(|| Price(notional / filled_quantity.0));
+        Ok(Self {
+            fills,
+            filled_quantity,
+            unfilled_quantity: quantity - filled_quantity,
+            average_price,
+        })
+    }
+}
+
+/// Expected execution of a hypothetical market order of a given size.
+#[derive(Clone, Debug)]
+pub struct SlippageEstimate {
+    pub best_price: Option<Price>,
+    pub average_price: Option<Price>,
+    /// Distance between the average execution price and the best price.
+    pub slippage: Price,
+    pub filled_quantity: Quantity,
+    pub unfilled_quantity: Quantity,
+}
+
+/// Result of sweeping one side of the book for a taker.
+struct Sweep {
+    now: u64,
+    maker_orders: Vec<Order>,
+    /// One per entry in `maker_orders`.
+    trades: Vec<Trade>,
+    /// Makers found past their expiry and removed instead of filled.
+    expired_orders: Vec<Order>,
+    remaining_quantity: Quantity,
+    levels_swept: u64,
+    /// Next arrival slot, for iceberg orders rejoining their level.
+    next_slot: u64,
+    /// Iceberg orders that moved to the back of their level, with their
+    /// new slot.
+    replenished: Vec<(OrderId, u64)>,
+    /// One-cancels-other partners of the taker and of makers filled so
+    /// far, which must not trade in the same sweep.
+    cancelled_links: HashSet<OrderId>,
+    /// Makers cancelled by self-trade prevention.
+    self_trade_cancelled: Vec<Order>,
+    /// Makers self-trade prevention shrank without cancelling, with their
+    /// new quantity.
+    decremented: Vec<(OrderId, Quantity)>,
+    /// Quantity self-trade prevention took off the taker without a fill.
+    taker_decrement: Quantity,
+    /// Whether self-trade prevention cancelled the rest of the taker.
+    taker_cancelled: bool,
+}
+
+impl Sweep {
+    fn fill_from<'a>(
+        &mut self,
+        levels: impl Iterator<Item = (&'a Price, &'a mut PriceLevel)>,
+        taker: Taker<'_>,
+        links: &HashMap<OrderId, OrderId>,
+        empty_price_levels: &mut Vec<Price>,
+    ) {
+        for (price_level, makers) in levels {
+            let makers_before = self.maker_orders.len();
+            // go through each maker in this price level, oldest first
+            let mut next_slot = 0;
+            while self.remaining_quantity > Quantity::ZERO {
+                let Some((&slot, maker)) = makers.range_mut(next_slot..).next() else {
+                    break;
+                };
+                next_slot = slot + 1;
+                if maker.is_expired(self.now) {
+                    self.expired_orders.push(makers.remove(&slot).unwrap());
+                    continue;
+                }
+                if self.cancelled_links.contains(&maker.id) {
+                    continue;
+                }
+                if let Some(policy) = taker.self_trade_with(maker) {
+                    // resolved before any fill is recorded
+                    let maker_remaining = maker.quantity - maker.filled_quantity;
+                    let cancel_maker = match policy {
+                        SelfTradePrevention::CancelNewest => false,
+                        SelfTradePrevention::DecrementAndCancel => {
+                            let decrement = maker_remaining.min(self.remaining_quantity);
+                            maker.quantity -= decrement;
+                            self.remaining_quantity -= decrement;
+                            self.taker_decrement += decrement;
+                            decrement == maker_remaining
+                        }
+                        _ => true,
+                    };
+                    if cancel_maker {
+                        let mut cancelled = makers.remove(&slot).unwrap();
+                        // report the order as it was before the decrement
+                        cancelled.quantity = cancelled.filled_quantity + maker_remaining;
+                        self.self_trade_cancelled.push(cancelled);
+                    } else if policy == SelfTradePrevention::DecrementAndCancel {
+                        self.decremented.push((maker.id, maker.quantity));
+                    }
+                    self.taker_cancelled = match policy {
+                        SelfTradePrevention::CancelNewest | SelfTradePrevention::CancelBoth => true,
+                        _ => self.remaining_quantity == Quantity::ZERO,
+                    };
+                    if self.taker_cancelled {
+                        break;
+                    }
+                    continue;
+                }
+                let maker_available_quantity = maker.visible_quantity();
+                if maker_available_quantity > self.remaining_quantity && maker.only_full_fill {
+                    continue;
+                }
+                self.cancelled_links.extend(links.get(&maker.id));
+                let fill_quantity = maker_available_quantity.min(self.remaining_quantity);
+                self.trades.push(Trade {
+                    maker_id: maker.id,
+                    taker_id: taker.id,
+                    price: *price_level,
+                    quantity: fill_quantity,
+                    timestamp: self.now,
+                    aggressor_side: taker.side,
+                });
+                if maker_available_quantity > self.remaining_quantity {
+                    // the maker order is only partially filled
+                    maker.filled_quantity += self.remaining_quantity;
+                    self.maker_orders.push(maker.clone());
+                    self.remaining_quantity = Quantity::ZERO;
+                } else if maker_available_quantity < maker.quantity - maker.filled_quantity {
+                    // an iceberg slice is used up; the next one joins the
+                    // back of the level
+                    let mut maker = makers.remove(&slot).unwrap();
+                    maker.filled_quantity += maker_available_quantity;
+                    self.maker_orders.push(maker.clone());
+                    self.remaining_quantity -= maker_available_quantity;
+                    self.replenished.push((maker.id, self.next_slot));
+                    makers.insert(self.next_slot, maker);
+                    self.next_slot += 1;
+                } else {
+                    // the maker order is completely filled
+                    let mut maker = makers.remove(&slot).unwrap();
+                    maker.filled_quantity = maker.quantity;
+                    self.maker_orders.push(maker);
+                    self.remaining_quantity -= maker_available_quantity;
+                }
+            }
+            if makers.is_empty() {
+                empty_price_levels.push(*price_level);
+            }
+            if self.maker_orders.len() > makers_before {
+                self.levels_swept += 1;
+            }
+            if self.remaining_quantity == Quantity::ZERO || self.taker_cancelled {
+                break;
+            }
+        }
+    }
+}
+
+/// Fills `quantity` for a taker on `taker_side` against the opposite
+/// `levels`, best price first, stopping at `limit_price`. Bids take asks
+/// from the lowest price up and asks take bids from the highest price
+/// down; both sides share the same fill rules. Market orders sweep with
+/// the far end of the price range, which bounds nothing. Expired
+/// makers met along the way are removed rather than filled, and levels
+/// emptied by the sweep are dropped.
+fn sweep_levels(
+    levels: &mut impl PriceLadder,
+    taker: Taker<'_>,
+    quantity: Quantity,
+    now: u64,
+    next_slot: u64,
+    links: &HashMap<OrderId, OrderId>,
+) -> Sweep {
+    let mut sweep = Sweep {
+        now,
+        maker_orders: Vec::new(),
+        trades: Vec::new(),
+        expired_orders: Vec::new(),
+        remaining_quantity: quantity,
+        levels_swept: 0,
+        next_slot,
+        replenished: Vec::new(),
+        cancelled_links: links.get(&taker.id).copied().into_iter().collect(),
+        self_trade_cancelled: Vec::new(),
+        decremented: Vec::new(),
+        taker_decrement: Quantity::ZERO,
+        taker_cancelled: false,
+    };
+    let mut empty_price_levels = Vec::new();
+    let limit_price = taker.limit_price;
+    match taker.side {
+        Side::Bid => sweep.fill_from(
+            levels.range_mut(..=limit_price),
+            taker,
+            links,
+            &mut empty_price_levels,
+        ),
+        Side::Ask => sweep.fill_from(
+            levels.range_mut(limit_price..).rev(),
+            taker,
+            links,
+            &mut empty_price_levels,
+        ),
+    }
+    for empty_price_level in empty_price_levels {
+        levels.remove(&empty_price_level);
+    }
+    sweep
+}
+
+impl<L: PriceLadder> OrderBook<L> {
+    /// Matches the market bid at `cursor` (or the next one that can execute)
+    /// against resting asks.
+    pub fn take_bid_order(&mut self, cursor: usize, now: u64) -> Option<Execution> {
+        self.take_order(Side::Bid, cursor, now)
+    }
+
+    /// Matches the market ask at `cursor` (or the next one that can execute)
+    /// against resting bids.
+    pub fn take_ask_order(&mut self, cursor: usize, now: u64) -> Option<Execution> {
+        self.take_order(Side::Ask, cursor, now)
+    }
+
+    fn take_order(&mut self, side: Side, mut cursor: usize, now: u64) -> Option<Execution> {
+        loop {
+            // take the oldest market order on this side
+            let (&slot, taker_order) = self.market_queue(side).iter().nth(cursor)?;
+            if taker_order.is_expired(now) {
+                let expired = self.market_queue_mut(side).remove(&slot).unwrap();
+                self.unindex([&expired]);
+                self.unlink(expired.id);
+                self.events.push(Event::Expired(expired.id));
+                self.expired_orders.push(expired);
+                continue;
+            }
+            let requested_quantity = taker_order.quantity - taker_order.filled_quantity;
+            let only_full_fill = taker_order.only_full_fill;
+            let taker_id = taker_order.id;
+            let limit_price = self.execution_limit(taker_order);
+            let bounded = limit_price != taker_order.price_bound();
+            let owner = taker_order.owner.clone();
+            let taker = Taker {
+                owner: &owner,
+                limit_price,
+                ..Taker::of(taker_order)
+            };
+
+            let Some(sweep) = self.execute(taker, requested_quantity, only_full_fill, now) else {
+                if bounded && self.has_liquidity_beyond(side, limit_price) {
+                    // everything left is outside the protection band
+                    let cancelled = self.market_queue_mut(side).remove(&slot).unwrap();
+                    self.unindex([&cancelled]);
+                    self.unlink(taker_id);
+                    self.cancelled(taker_id, CancelReason::PriceBand);
+                    self.price_band_cancellations.push(cancelled);
+                    continue;
+                }
+                if only_full_fill {
+                    cursor += 1;
+                    continue;
+                }
+                return None;
+            };
+
+            let executed_quantity =
+                requested_quantity - sweep.remaining_quantity - sweep.taker_decrement;
+            if !sweep.maker_orders.is_empty() {
+                self.cancel_linked(taker_id);
+            }
+            let band_breached = bounded
+                && !sweep.taker_cancelled
+                && sweep.remaining_quantity > Quantity::ZERO
+                && self.has_liquidity_beyond(side, limit_price);
+            let queue = self.market_queue_mut(side);
+            let taker_order = queue.get_mut(&slot).unwrap();
+            taker_order.quantity -= sweep.taker_decrement;
+            taker_order.filled_quantity += executed_quantity;
+            let done = sweep.taker_cancelled
+                || band_breached
+                || taker_order.filled_quantity == taker_order.quantity;
+            if sweep.taker_decrement > Quantity::ZERO {
+                let quantity = taker_order.quantity;
+                self.events.push(Event::Resized {
+                    order_id: taker_id,
+                    quantity,
+                });
+            }
+            let queue = self.market_queue_mut(side);
+            let taker_order = if done {
+                let taker_order = queue.remove(&slot).unwrap();
+                self.unindex([&taker_order]);
+                if sweep.taker_cancelled {
+                    self.unlink(taker_id);
+                    self.cancelled(taker_id, CancelReason::SelfTrade);
+                    self.self_trade_cancellations.push(taker_order.clone());
+                } else if band_breached {
+                    self.unlink(taker_id);
+                    self.cancelled(taker_id, CancelReason::PriceBand);
+                    self.price_band_cancellations.push(taker_order.clone());
+                }
+                taker_order
+            } else {
+                queue[&slot].clone()
+            };
+            if sweep.maker_orders.is_empty() {
+                // self-trade prevention acted without a fill
+                if done {
+                    continue;
+                }
+                return None;
+            }
+            return Some(Execution {
+                taker: taker_order,
+                makers: sweep.maker_orders,
+                trades: sweep.trades,
+            });
+        }
+    }
+
+    /// Matches an incoming order against the opposite side up to its limit
+    /// price. Returns the execution, with no trades if nothing matched, and
+    /// whether self-trade prevention cancelled the order's remainder;
+    /// otherwise the caller decides what happens to the remainder.
+    pub(crate) fn match_incoming_order(&mut self, mut order: Order, now: u64) -> (Execution, bool) {
+        let requested_quantity = order.quantity - order.filled_quantity;
+        let taker = Taker {
+            limit_price: self.execution_limit(&order),
+            ..Taker::of(&order)
+        };
+        let Some(sweep) = self.execute(taker, requested_quantity, order.requires_full_fill(), now)
+        else {
+            let execution = Execution {
+                taker: order,
+                makers: Vec::new(),
+                trades: Vec::new(),
+            };
+            return (execution, false);
+        };
+        order.quantity -= sweep.taker_decrement;
+        order.filled_quantity +=
+            requested_quantity - sweep.remaining_quantity - sweep.taker_decrement;
+        let execution = Execution {
+            taker: order,
+            makers: sweep.maker_orders,
+            trades: sweep.trades,
+        };
+        (execution, sweep.taker_cancelled)
+    }
+
+    /// Sweeps the opposite side for a taker and records the match. Returns
+    /// `None` without touching the book when nothing executes or
+    /// self-trade prevention doesn't step in, or when an only-full-fill
+    /// taker can't be filled in full.
+    fn execute(
+        &mut self,
+        taker: Taker<'_>,
+        quantity: Quantity,
+        only_full_fill: bool,
+        now: u64,
+    ) -> Option<Sweep> {
+        if only_full_fill && total_quantity(&self.simulate(taker, quantity, now)) < quantity {
+            return None;
+        }
+        let makers = match taker.side {
+            Side::Bid => &mut self.asks,
+            Side::Ask => &mut self.bids,
+        };
+        let mut sweep = sweep_levels(makers, taker, quantity, now, self.next_slot, &self.links);
+        self.next_slot = sweep.next_slot;
+        self.log_sweep(&sweep);
+        for (order_id, slot) in &sweep.replenished {
+            if let Some(location) = self.index.get_mut(order_id) {
+                location.slot = *slot;
+            }
+        }
+        self.unindex(&sweep.expired_orders);
+        for expired in &sweep.expired_orders {
+            self.unlink(expired.id);
+        }
+        self.expired_orders.append(&mut sweep.expired_orders);
+        // partially filled makers stay on the book and keep their entry
+        self.unindex(
+            sweep
+                .maker_orders
+                .iter()
+                .filter(|maker| maker.filled_quantity == maker.quantity),
+        );
+        let prevented_self_trade = sweep.taker_cancelled
+            || sweep.taker_decrement > Quantity::ZERO
+            || !sweep.self_trade_cancelled.is_empty();
+        self.unindex(&sweep.self_trade_cancelled);
+        for cancelled in &sweep.self_trade_cancelled {
+            self.unlink(cancelled.id);
+        }
+        self.self_trade_cancellations
+            .append(&mut sweep.self_trade_cancelled);
+        if sweep.maker_orders.is_empty() {
+            return prevented_self_trade.then_some(sweep);
+        }
+        for maker in &sweep.maker_orders {
+            self.cancel_linked(maker.id);
+        }
+        self.stats.record_match(
+            quantity,
+            quantity - sweep.remaining_quantity - sweep.taker_decrement,
+            sweep.levels_swept,
+        );
+        for maker in &sweep.maker_orders {
+            self.stats.record_maker_fill(maker.rested_at, now);
+        }
+        if let Some(last_trade) = sweep.trades.last() {
+            self.last_price_level = last_trade.price;
+        }
+        Some(sweep)
+    }
+
+    /// Logs what a sweep did to the makers. Their fills are applied before
+    /// iceberg slices move, so trades come ahead of replenishments.
+    fn log_sweep(&mut self, sweep: &Sweep) {
+        let expired = sweep
+            .expired_orders
+            .iter()
+            .map(|order| Event::Expired(order.id));
+        let self_trades = sweep
+            .self_trade_cancelled
+            .iter()
+            .map(|order| Event::Cancelled {
+                order_id: order.id,
+                reason: CancelReason::SelfTrade,
+            });
+        let decremented = sweep
+            .decremented
+            .iter()
+            .map(|&(order_id, quantity)| Event::Resized { order_id, quantity });
+        let trades = sweep.trades.iter().cloned().map(Event::Traded);
+        let replenished = sweep
+            .replenished
+            .iter()
+            .map(|&(order_id, slot)| Event::Replenished { order_id, slot });
+        self.events.extend(
+            expired
+                .chain(self_trades)
+                .chain(decremented)
+                .chain(trades)
+                .chain(replenished),
+        );
+    }
+
+    /// Matches queued market orders on both sides until none can execute.
+    pub fn match_market_orders(&mut self, now: u64) -> Vec<Execution> {
+        let mut matches = Vec::new();
+        while let Some(matched) = self
+            .take_bid_order(0, now)
+            .or_else(|| self.take_ask_order(0, now))
+        {
+            matches.push(matched);
+        }
+        matches
+    }
+
+    /// Activates stop orders whose stop price has been reached by the last
+    /// traded price: stop bids at or below it and stop asks at or above it.
+    /// Each becomes a market or limit order and is matched straight away.
+    /// Trades caused by triggered orders can trigger further stops, so
+    /// passes repeat until one triggers nothing.
+    ///
+    /// Within a pass, stop bids are activated first in ascending stop price,
+    /// then stop asks in descending stop price, oldest first within a level,
+    /// so cascades resolve the same way on every replica. Stops that expired
+    /// before triggering are set aside for `expire` instead. Triggering one
+    /// leg of a one-cancels-other pair cancels the other, even if it was
+    /// triggered in the same pass.
+    pub fn trigger_stops(&mut self, now: u64) -> Vec<Execution> {
+        let mut matches = Vec::new();
+        loop {
+            let triggered = self.take_triggered_stops();
+            if triggered.is_empty() {
+                return matches;
+            }
+            // partners of orders triggered in this pass that haven't been
+            // reached yet, with the order that cancelled them
+            let mut cancelled_by = HashMap::new();
+            for mut order in triggered {
+                if let Some(cause) = cancelled_by.remove(&order.id) {
+                    self.cancelled(order.id, CancelReason::Linked);
+                    self.linked_cancellations.push((cause, order));
+                    continue;
+                }
+                if order.is_expired(now) {
+                    self.unlink(order.id);
+                    self.events.push(Event::Expired(order.id));
+                    self.expired_orders.push(order);
+                    continue;
+                }
+                self.events.push(Event::Triggered(order.id));
+                if let Some(partner) = self.cancel_linked(order.id) {
+                    cancelled_by.insert(partner, order.id);
+                }
+                let order_type = order.trigger();
+                matches.extend(self.insert_order(order, order_type, now));
+                matches.extend(self.match_market_orders(now));
+            }
+        }
+    }
+
+    fn take_triggered_stops(&mut self) -> Vec<Order> {
+        let last_price = self.last_price_level;
+        let bid_levels: Vec<Price> = self
+            .stop_bids
+            .range(..=last_price)
+            .map(|(stop_price, _)| *stop_price)
+            .collect();
+        let ask_levels: Vec<Price> = self
+            .stop_asks
+            .range(last_price..)
+            .rev()
+            .map(|(stop_price, _)| *stop_price)
+            .collect();
+
+        let mut triggered = Vec::new();
+        for stop_price in bid_levels {
+            triggered.extend(
+                self.stop_bids
+                    .remove(&stop_price)
+                    .unwrap_or_default()
+                    .into_values(),
+            );
+        }
+        for stop_price in ask_levels {
+            triggered.extend(
+                self.stop_asks
+                    .remove(&stop_price)
+                    .unwrap_or_default()
+                    .into_values(),
+            );
+        }
+        self.unindex(&triggered);
+        triggered
+    }
+
+    fn market_queue(&self, side: Side) -> &PriceLevel {
+        match side {
+            Side::Bid => &self.market_bids,
+            Side::Ask => &self.market_asks,
+        }
+    }
+
+    fn market_queue_mut(&mut self, side: Side) -> &mut PriceLevel {
+        match side {
+            Side::Bid => &mut self.market_bids,
+            Side::Ask => &mut self.market_asks,
+        }
+    }
+
+    /// Worst price `order` may execute at. For market orders that is the
+    /// tighter of the book's price band around the last traded price and
+    /// the order's own slippage limit from the best opposite price; other
+    /// orders are bounded by their limit price alone.
+    pub(crate) fn execution_limit(&self, order: &Order) -> Price {
+        if order.order_type != OrderType::Market {
+            return order.price_bound();
+        }
+        let best_price = match order.side {
+            Side::Bid => self.asks.keys().next(),
+            Side::Ask => self.bids.keys().next_back(),
+        };
+        let band = self.price_band_bps.map(|bps| (self.last_price_level, bps));
+        let slippage = order
+            .max_slippage_bps
+            .zip(best_price)
+            .map(|(bps, best_price)| (*best_price, bps));
+
+        let mut limit_price = order.price_bound();
+        for (Price(reference), bps) in [band, slippage].into_iter().flatten() {
+            let scale = U256::from(10_000);
+            let bps = U256::from(bps);
+            let deviation = Price(
+                reference
+                    .checked_mul(bps)
+                    .map_or_else(|| reference / scale * bps, |scaled| scaled / scale),
+            );
+            let reference = Price(reference);
+            limit_price = match order.side {
+                Side::Bid => limit_price.min(reference.saturating_add(deviation)),
+                Side::Ask => limit_price.max(reference.saturating_sub(deviation)),
+            };
+        }
+        limit_price
+    }
+
+    /// Whether the side opposite `taker_side` has liquidity priced worse
+    /// than `limit_price`.
+    fn has_liquidity_beyond(&self, taker_side: Side, limit_price: Price) -> bool {
+        match taker_side {
+            Side::Bid => self
+                .asks
+                .keys()
+                .next_back()
+                .is_some_and(|worst_ask| *worst_ask > limit_price),
+            Side::Ask => self
+                .bids
+                .keys()
+                .next()
+                .is_some_and(|worst_bid| *worst_bid < limit_price),
+        }
+    }
+
+    /// Whether a limit order on `side` at `limit_price` would trade against
+    /// the best opposite price.
+    pub(crate) fn crosses_spread(&self, side: Side, limit_price: Price) -> bool {
+        match side {
+            Side::Bid => self
+                .asks
+                .keys()
+                .next()
+                .is_some_and(|best_ask| *best_ask <= limit_price),
+            Side::Ask => self
+                .bids
+                .keys()
+                .next_back()
+                .is_some_and(|best_bid| *best_bid >= limit_price),
+        }
+    }
+
+    /// Fills `taker` would get for `quantity` at `now`, without mutating
+    /// the book.
+    pub(crate) fn simulate(
+        &self,
+        taker: Taker<'_>,
+        quantity: Quantity,
+        now: u64,
+    ) -> Vec<PreviewFill> {
+        let limit_price = taker.limit_price;
+        match taker.side {
+            Side::Bid => simulate_sweep(
+                self.asks.range(..=limit_price),
+                taker,
+                quantity,
+                now,
+                &self.links,
+            ),
+            Side::Ask => simulate_sweep(
+                self.bids.range(limit_price..).rev(),
+                taker,
+                quantity,
+                now,
+                &self.links,
+            ),
+        }
+    }
+
+    /// Simulates matching `order` against the current book without changing
+    /// any state. Fees aren't modelled yet, so the preview only covers fills.
+    pub fn preview_order(&self, order: &Order, now: u64) -> Result<OrderPreview> {
+        let order_type = order.validate()?;
+        // stop orders only match once triggered
+        if !matches!(order_type, OrderType::Market | OrderType::Limit) {
+            bail!("Only market and limit orders can be previewed");
+        }
+
+        let quantity = order.quantity - order.filled_quantity;
+        let taker = Taker {
+            limit_price: self.execution_limit(order),
+            ..Taker::of(order)
+        };
+        let fills = self.simulate(taker, quantity, now);
+        let preview = OrderPreview::from_fills(fills, quantity)?;
+        if order.requires_full_fill() && preview.unfilled_quantity > Quantity::ZERO {
+            return OrderPreview::from_fills(Vec::new(), quantity);
+        }
+        Ok(preview)
+    }
+
+    /// Estimates how a market order of `quantity` on `side` would execute
+    /// against the current depth at `now`.
+    pub fn estimate_slippage(
+        &self,
+        side: Side,
+        quantity: Quantity,
+        now: u64,
+    ) -> Result<SlippageEstimate> {
+        let (best_price, limit_price) = match side {
+            Side::Bid => (self.asks.keys().next().copied(), Price::MAX),
+            Side::Ask => (self.bids.keys().next_back().copied(), Price::ZERO),
+        };
+        let taker = Taker {
+            id: OrderId::default(),
+            side,
+            owner: "",
+            limit_price,
+            self_trade_prevention: SelfTradePrevention::Allow,
+        };
+        let fills = self.simulate(taker, quantity, now);
+        let preview = OrderPreview::from_fills(fills, quantity)?;
+        let slippage = match (best_price, preview.average_price) {
+            (Some(best_price), Some(average_price)) => {
+                Price(best_price.0.abs_diff(average_price.0))
+            }
+            _ => Price::ZERO,
+        };
+        Ok(SlippageEstimate {
+            best_price,
+            average_price: preview.average_price,
+            slippage,
+            filled_quantity: preview.filled_quantity,
+            unfilled_quantity: preview.unfilled_quantity,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::order::TimeInForce;
+    use crate::test_utils::*;
+
+    #[test]
+    fn matches_max_quantity_without_wrapping() {
+        let mut book = OrderBook::from_initial_price(Price(U256::from(1)));
+        book.add_order(limit_ask(U256::MAX, U256::from(1)), 0)
+            .unwrap();
+        book.add_order(market_bid(U256::MAX), 0).unwrap();
+
+        let Execution { taker, makers, .. } = book.take_bid_order(0, 0).unwrap();
+        assert_eq!(taker.quantity, Quantity::MAX);
+        assert_eq!(makers.len(), 1);
+        assert!(book.market_bids.is_empty());
+
+        book.add_order(limit_ask(U256::MAX, U256::from(1)), 0)
+            .unwrap();
+        book.add_order(market_bid(U256::MAX), 0).unwrap();
+        book.take_bid_order(0, 0).unwrap();
+        assert_eq!(book.stats().requested_quantity, Quantity::MAX);
+    }
+
+    #[test]
+    fn stats_count_fills_sweeps_and_maker_resting_time() {
+        let mut book = OrderBook::from_initial_price(Price(U256::from(100)));
+        assert_eq!(book.stats().fill_ratio(), 0.0);
+        assert_eq!(book.stats().average_sweep_depth(), 0.0);
+        assert_eq!(book.stats().average_maker_resting_time(), 0.0);
+        book.add_order(limit_ask(U256::from(2), U256::from(101)), 0)
+            .unwrap();
+        book.add_order(limit_ask(U256::from(2), U256::from(102)), 10)
+            .unwrap();
+
+        book.add_order(market_bid(U256::from(3)), 30).unwrap();
+        book.match_market_orders(30);
+        book.add_order(market_bid(U256::from(5)), 40).unwrap();
+        book.match_market_orders(40);
+
+        let stats = book.stats();
+        assert_eq!(stats.taker_matches, 2);
+        assert_eq!(stats.levels_swept, 3);
+        assert_eq!(stats.requested_quantity, Quantity(U256::from(8)));
+        assert_eq!(stats.executed_quantity, Quantity(U256::from(4)));
+        assert_eq!(stats.maker_fills, 3);
+        assert_eq!(stats.maker_resting_time, 30 + 20 + 30);
+        assert_eq!(stats.fill_ratio(), 0.5);
+        assert_eq!(stats.average_sweep_depth(), 1.5);
+        assert_eq!(stats.average_maker_resting_time(), 80.0 / 3.0);
+    }
+
+    #[test]
+    fn market_ask_sweeps_bids_from_best_price_down() {
+        let mut book = OrderBook::from_initial_price(Price(U256::from(100)));
+        book.add_order(limit_bid(U256::from(2), U256::from(98)), 0)
+            .unwrap();
+        book.add_order(limit_bid(U256::from(2), U256::from(99)), 0)
+            .unwrap();
+        book.add_order(market_ask(U256::from(3)), 0).unwrap();
+
+        let Execution {
+            taker,
+            makers,
+            trades,
+        } = book.take_ask_order(0, 0).unwrap();
+        assert_eq!(taker.filled_quantity, Quantity(U256::from(3)));
+        assert_eq!(makers[0].limit_price, Some(Price(U256::from(99))));
+        assert_eq!(makers[1].limit_price, Some(Price(U256::from(98))));
+        assert_eq!(makers[1].filled_quantity, Quantity(U256::from(1)));
+        assert_eq!(trades[0].maker_id, makers[0].id);
+        assert_eq!(trades[0].taker_id, taker.id);
+        assert_eq!(trades[1].price, Price(U256::from(98)));
+        assert_eq!(trades[1].quantity, Quantity(U256::from(1)));
+        assert_eq!(trades[1].aggressor_side, Side::Ask);
+        assert_eq!(book.last_price_level(), Price(U256::from(98)));
+        assert!(!book.bids.contains_key(&Price(U256::from(99))));
+        assert!(book.market_asks.is_empty());
+    }
+
+    #[test]
+    fn only_full_fill_taker_leaves_book_untouched_when_it_cannot_fill() {
+        let mut book = OrderBook::from_initial_price(Price(U256::from(100)));
+        book.add_order(limit_ask(U256::from(2), U256::from(101)), 0)
+            .unwrap();
+        let mut taker = market_bid(U256::from(5));
+        taker.only_full_fill = true;
+        book.add_order(taker, 0).unwrap();
+
+        assert!(book.take_bid_order(0, 0).is_none());
+        assert_eq!(
+            book.asks[&Price(U256::from(101))]
+                .first_key_value()
+                .unwrap()
+                .1
+                .filled_quantity,
+            Quantity::ZERO
+        );
+    }
+
+    #[test]
+    fn trades_trigger_cascading_stops() {
+        let mut book = OrderBook::from_initial_price(Price(U256::from(100)));
+        book.add_order(limit_ask(U256::from(1), U256::from(101)), 0)
+            .unwrap();
+        book.add_order(limit_ask(U256::from(1), U256::from(102)), 0)
+            .unwrap();
+        book.add_order(limit_ask(U256::from(1), U256::from(103)), 0)
+            .unwrap();
+        // triggers at 101, buys at 102, which triggers the stop at 102
+        book.add_order(stop_bid(U256::from(1), U256::from(101)), 0)
+            .unwrap();
+        book.add_order(stop_bid(U256::from(1), U256::from(102)), 0)
+            .unwrap();
+        book.add_order(stop_ask(U256::from(1), U256::from(90)), 0)
+            .unwrap();
+        assert!(book.trigger_stops(0).is_empty());
+
+        book.add_order(market_bid(U256::from(1)), 0).unwrap();
+        book.match_market_orders(0);
+        assert_eq!(book.last_price_level(), Price(U256::from(101)));
+
+        let triggered = book.trigger_stops(0);
+        assert_eq!(triggered.len(), 2);
+        assert_eq!(book.last_price_level(), Price(U256::from(103)));
+        assert!(book.stop_bids.is_empty());
+        assert!(book.asks.is_empty());
+        assert_eq!(book.stop_asks.len(), 1);
+        assert_eq!(book.index.len(), 1);
+    }
+
+    #[test]
+    fn iceberg_slices_rejoin_the_back_of_their_level() {
+        let mut book = OrderBook::from_initial_price(Price(U256::from(100)));
+        let mut iceberg = limit_ask(U256::from(5), U256::from(101));
+        iceberg.display_quantity = Quantity(U256::from(2));
+        let (iceberg, _) = book.add_order(iceberg, 0).unwrap();
+        let (plain, _) = book
+            .add_order(limit_ask(U256::from(1), U256::from(101)), 0)
+            .unwrap();
+
+        let preview = book.preview_order(&market_bid(U256::from(4)), 0).unwrap();
+        let filled: Vec<_> = preview.fills.iter().map(|fill| fill.quantity).collect();
+        assert_eq!(
+            filled,
+            [2, 1, 1].map(|quantity| Quantity(U256::from(quantity)))
+        );
+
+        book.add_order(market_bid(U256::from(4)), 0).unwrap();
+        let makers = book.take_bid_order(0, 0).unwrap().makers;
+        let ids: Vec<_> = makers.iter().map(|maker| maker.id).collect();
+        assert_eq!(ids, [iceberg, plain, iceberg]);
+        let rest = book.order(iceberg).unwrap();
+        assert_eq!(rest.filled_quantity, Quantity(U256::from(3)));
+        assert_eq!(rest.visible_quantity(), Quantity(U256::from(1)));
+    }
+
+    #[test]
+    fn self_trade_prevention_resolves_before_filling() {
+        use crate::order::SelfTradePrevention;
+
+        let mut book = OrderBook::from_initial_price(Price(U256::from(100)));
+        let (own, _) = book
+            .add_order(limit_ask(U256::from(2), U256::from(101)), 0)
+            .unwrap();
+        let mut other = limit_ask(U256::from(2), U256::from(102));
+        other.owner = "other".to_string();
+        book.add_order(other, 0).unwrap();
+
+        let mut taker = limit_bid(U256::from(1), U256::from(102));
+        taker.self_trade_prevention = SelfTradePrevention::CancelNewest;
+        assert!(book.add_order(taker.clone(), 0).unwrap().1.is_none());
+        assert_eq!(book.take_self_trade_cancellations().len(), 1);
+        assert!(book.order(own).is_some());
+        assert!(book.bids.is_empty());
+
+        taker.quantity = Quantity(U256::from(3));
+        taker.self_trade_prevention = SelfTradePrevention::DecrementAndCancel;
+        let Execution { taker, makers, .. } = book.add_order(taker, 0).unwrap().1.unwrap();
+        assert_eq!(makers.len(), 1);
+        assert_eq!(makers[0].owner, "other");
+        assert_eq!(taker.quantity, Quantity(U256::from(1)));
+        assert_eq!(taker.filled_quantity, Quantity(U256::from(1)));
+        let cancelled = book.take_self_trade_cancellations();
+        assert_eq!(cancelled.len(), 1);
+        assert_eq!(cancelled[0].id, own);
+        assert_eq!(book.stats().executed_quantity, Quantity(U256::from(1)));
+    }
+
+    #[test]
+    fn market_orders_stop_at_the_price_band() {
+        let mut book = OrderBook::from_initial_price(Price(U256::from(100))).with_price_band(200);
+        book.add_order(limit_ask(U256::from(1), U256::from(101)), 0)
+            .unwrap();
+        book.add_order(limit_ask(U256::from(2), U256::from(103)), 0)
+            .unwrap();
+
+        let (market, _) = book.add_order(market_bid(U256::from(3)), 0).unwrap();
+        let Execution { taker, makers, .. } = book.take_bid_order(0, 0).unwrap();
+        assert_eq!(taker.filled_quantity, Quantity(U256::from(1)));
+        assert_eq!(makers.len(), 1);
+        assert!(book.market_bids.is_empty());
+        assert_eq!(book.take_price_band_cancellations()[0].id, market);
+
+        // a per-order limit of zero only takes the best price
+        book.add_order(limit_ask(U256::from(1), U256::from(102)), 0)
+            .unwrap();
+        let mut ioc = market_bid(U256::from(3));
+        ioc.time_in_force = TimeInForce::ImmediateOrCancel;
+        ioc.max_slippage_bps = Some(0);
+        let taker = book.add_order(ioc, 0).unwrap().1.unwrap().taker;
+        assert_eq!(taker.filled_quantity, Quantity(U256::from(1)));
+        assert!(book.asks.contains_key(&Price(U256::from(103))));
+    }
+
+    #[test]
+    fn preview_reports_notional_overflow() {
+        let mut book = OrderBook::from_initial_price(Price(U256::from(100)));
+        book.add_order(limit_ask(U256::MAX, U256::from(2)), 0)
+            .unwrap();
+        let err = book.preview_order(&market_bid(U256::MAX), 0).unwrap_err();
+        assert_eq!(err.to_string(), "Notional overflow");
+    }
+
+    #[test]
+    fn preview_rejects_corrupted_order() {
+        let book = OrderBook::from_initial_price(Price(U256::from(100)));
+        let mut corrupted = market_bid(U256::from(1));
+        corrupted.filled_quantity = Quantity::MAX;
+        assert!(book.preview_order(&corrupted, 0).is_err());
+    }
+}
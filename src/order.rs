@@ -0,0 +1,309 @@
+use alloy::primitives::U256;
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::units::{Price, Quantity};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Side {
+    Bid,
+    Ask,
+}
+
+/// How long an order keeps working before its unfilled quantity is
+/// cancelled.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum TimeInForce {
+    /// Rests until filled or cancelled.
+    #[default]
+    GoodTilCancelled,
+    /// Executes what it can on arrival; the remainder is cancelled.
+    ImmediateOrCancel,
+    /// Executes in full on arrival or is cancelled without trading.
+    FillOrKill,
+    /// Rests until `expire_timestamp`.
+    GoodTilDate,
+}
+
+impl TimeInForce {
+    /// Whether the order only trades on arrival and never rests or queues.
+    pub fn is_immediate(self) -> bool {
+        matches!(self, Self::ImmediateOrCancel | Self::FillOrKill)
+    }
+}
+
+/// Identifier the book assigns to each order it accepts.
+#[derive(
+    Clone, Copy, Debug, Default, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize,
+)]
+pub struct OrderId(pub u64);
+
+/// What happens when an order would trade against a resting order of the
+/// same owner. The incoming order's setting applies.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum SelfTradePrevention {
+    /// Self-trades are allowed.
+    #[default]
+    Allow,
+    /// Cancel the remainder of the incoming order.
+    CancelNewest,
+    /// Cancel the resting order and keep matching.
+    CancelOldest,
+    /// Cancel the resting order and the remainder of the incoming order.
+    CancelBoth,
+    /// Reduce both orders by the smaller of their remaining quantities,
+    /// cancelling whichever reaches zero, and keep matching if the
+    /// incoming order has quantity left.
+    DecrementAndCancel,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Order {
+    /// Assigned by `OrderBook::add_order`; any value set beforehand is
+    /// replaced.
+    pub id: OrderId,
+    pub owner: String,
+    pub nonce: U256,
+    pub quantity: Quantity,
+    pub filled_quantity: Quantity,
+    pub order_type: OrderType,
+    /// Worst price the order trades at. Set for limit and stop-limit
+    /// orders only.
+    pub limit_price: Option<Price>,
+    /// Last traded price that activates the order. Set for stop and
+    /// stop-limit orders only.
+    pub stop_price: Option<Price>,
+    pub expire_timestamp: u64,
+    pub side: Side,
+    pub only_full_fill: bool,
+    pub time_in_force: TimeInForce,
+    /// Rejected rather than matched if it would cross the spread on
+    /// arrival, so the order can only ever provide liquidity.
+    pub post_only: bool,
+    /// Size of the visible slice of an iceberg order; the rest is held in
+    /// reserve. Zero shows the whole order.
+    pub display_quantity: Quantity,
+    pub self_trade_prevention: SelfTradePrevention,
+    /// Furthest a market order may execute from the best opposite price at
+    /// the time it matches, in basis points.
+    pub max_slippage_bps: Option<u16>,
+    /// Lets the order past a `DuplicateGuard` when it deliberately repeats
+    /// a recent one.
+    pub allow_duplicate: bool,
+    /// When the order last joined the back of a limit level. Set by the
+    /// book; any value set beforehand is replaced.
+    pub rested_at: u64,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum OrderType {
+    Market,
+    Limit,
+    Stop,
+    StopLimit,
+}
+
+impl Order {
+    /// Starts building an order; the type follows from which prices are
+    /// set. See `OrderBuilder`.
+    pub fn builder(owner: impl Into<String>, side: Side, quantity: Quantity) -> OrderBuilder {
+        OrderBuilder {
+            order: Order {
+                id: OrderId::default(),
+                owner: owner.into(),
+                nonce: U256::ZERO,
+                quantity,
+                filled_quantity: Quantity::ZERO,
+                order_type: OrderType::Market,
+                limit_price: None,
+                stop_price: None,
+                expire_timestamp: 0,
+                side,
+                only_full_fill: false,
+                time_in_force: TimeInForce::GoodTilCancelled,
+                post_only: false,
+                display_quantity: Quantity::ZERO,
+                self_trade_prevention: SelfTradePrevention::Allow,
+                max_slippage_bps: None,
+                allow_duplicate: false,
+                rested_at: 0,
+            },
+        }
+    }
+
+    /// Checks the order is safe to hand to the matcher and returns its type.
+    /// Quantities are unsigned, so an order whose filled quantity exceeds its
+    /// quantity would silently wrap when the remainder is computed. Fields
+    /// are public, so this runs again whenever an order enters the book.
+    pub fn validate(&self) -> Result<OrderType> {
+        if self.quantity == Quantity::ZERO {
+            bail!("Order quantity is zero");
+        }
+        if self.filled_quantity > self.quantity {
+            bail!("Filled quantity exceeds order quantity");
+        }
+        if self.filled_quantity == self.quantity {
+            bail!("Order is already filled");
+        }
+        if self.time_in_force == TimeInForce::GoodTilDate && self.expire_timestamp == 0 {
+            bail!("Good-til-date order has no expiry");
+        }
+        let order_type = self.order_type;
+        let prices_match_type = match order_type {
+            OrderType::Market => self.limit_price.is_none() && self.stop_price.is_none(),
+            OrderType::Limit => self.limit_price.is_some() && self.stop_price.is_none(),
+            OrderType::Stop => self.limit_price.is_none() && self.stop_price.is_some(),
+            OrderType::StopLimit => self.limit_price.is_some() && self.stop_price.is_some(),
+        };
+        if !prices_match_type {
+            bail!("Order prices do not match its type");
+        }
+        if self.limit_price == Some(Price::ZERO) || self.stop_price == Some(Price::ZERO) {
+            bail!("Order price is zero");
+        }
+        if self.post_only && (order_type != OrderType::Limit || self.time_in_force.is_immediate()) {
+            bail!("Post-only order must be a resting limit order");
+        }
+        if self.display_quantity > Quantity::ZERO
+            && (order_type != OrderType::Limit
+                || self.time_in_force.is_immediate()
+                || self.only_full_fill)
+        {
+            bail!("Iceberg order must be a resting limit order");
+        }
+        if self.max_slippage_bps.is_some()
+            && !matches!(order_type, OrderType::Market | OrderType::Stop)
+        {
+            bail!("Slippage limit only applies to market orders");
+        }
+        Ok(order_type)
+    }
+
+    /// Quantity currently shown and matchable: what remains of the current
+    /// slice for an iceberg order, and everything that remains otherwise.
+    pub fn visible_quantity(&self) -> Quantity {
+        self.visible_at(self.filled_quantity)
+    }
+
+    /// Visible quantity once `filled_quantity` has been filled. Slices are
+    /// cut from the start of the order, so the current one follows from
+    /// the fills alone.
+    pub(crate) fn visible_at(&self, filled_quantity: Quantity) -> Quantity {
+        let remaining = self.quantity - filled_quantity;
+        if self.display_quantity == Quantity::ZERO {
+            return remaining;
+        }
+        let slice_filled = Quantity(filled_quantity.0 % self.display_quantity.0);
+        (self.display_quantity - slice_filled).min(remaining)
+    }
+
+    /// Whether a good-til-date order has reached its expiry at `now`.
+    pub fn is_expired(&self, now: u64) -> bool {
+        self.time_in_force == TimeInForce::GoodTilDate && self.expire_timestamp <= now
+    }
+
+    /// Whether the order may only trade if its whole remaining quantity
+    /// executes at once.
+    pub fn requires_full_fill(&self) -> bool {
+        self.only_full_fill || self.time_in_force == TimeInForce::FillOrKill
+    }
+
+    /// Price bound to sweep the opposite side with: the limit price, or
+    /// the far end of the price range for market orders.
+    pub(crate) fn price_bound(&self) -> Price {
+        self.limit_price.unwrap_or(match self.side {
+            Side::Bid => Price::MAX,
+            Side::Ask => Price::ZERO,
+        })
+    }
+
+    /// Turns a triggered stop or stop-limit order into the market or limit
+    /// order it stands for by clearing its stop price.
+    pub(crate) fn trigger(&mut self) -> OrderType {
+        self.stop_price = None;
+        self.order_type = match self.order_type {
+            OrderType::Stop => OrderType::Market,
+            OrderType::StopLimit => OrderType::Limit,
+            order_type => order_type,
+        };
+        self.order_type
+    }
+}
+
+/// Builds an `Order`. Setting a limit price makes a limit order and a stop
+/// price a stop order; both make a stop-limit order and neither a market
+/// order. `build` rejects combinations the book would refuse.
+#[derive(Clone, Debug)]
+pub struct OrderBuilder {
+    order: Order,
+}
+
+impl OrderBuilder {
+    pub fn limit(mut self, limit_price: Price) -> Self {
+        self.order.limit_price = Some(limit_price);
+        self
+    }
+
+    pub fn stop(mut self, stop_price: Price) -> Self {
+        self.order.stop_price = Some(stop_price);
+        self
+    }
+
+    pub fn nonce(mut self, nonce: U256) -> Self {
+        self.order.nonce = nonce;
+        self
+    }
+
+    pub fn time_in_force(mut self, time_in_force: TimeInForce) -> Self {
+        self.order.time_in_force = time_in_force;
+        self
+    }
+
+    /// Makes the order good-til-date, expiring at `expire_timestamp`.
+    pub fn good_til(mut self, expire_timestamp: u64) -> Self {
+        self.order.time_in_force = TimeInForce::GoodTilDate;
+        self.order.expire_timestamp = expire_timestamp;
+        self
+    }
+
+    pub fn only_full_fill(mut self) -> Self {
+        self.order.only_full_fill = true;
+        self
+    }
+
+    pub fn post_only(mut self) -> Self {
+        self.order.post_only = true;
+        self
+    }
+
+    pub fn display_quantity(mut self, display_quantity: Quantity) -> Self {
+        self.order.display_quantity = display_quantity;
+        self
+    }
+
+    pub fn self_trade_prevention(mut self, self_trade_prevention: SelfTradePrevention) -> Self {
+        self.order.self_trade_prevention = self_trade_prevention;
+        self
+    }
+
+    pub fn max_slippage_bps(mut self, max_slippage_bps: u16) -> Self {
+        self.order.max_slippage_bps = Some(max_slippage_bps);
+        self
+    }
+
+    pub fn allow_duplicate(mut self) -> Self {
+        self.order.allow_duplicate = true;
+        self
+    }
+
+    pub fn build(mut self) -> Result<Order> {
+        self.order.order_type = match (self.order.limit_price, self.order.stop_price) {
+            (None, None) => OrderType::Market,
+            (Some(_), None) => OrderType::Limit,
+            (None, Some(_)) => OrderType::Stop,
+            (Some(_), Some(_)) => OrderType::StopLimit,
+        };
+        self.order.validate()?;
+        Ok(self.order)
+    }
+}
@@ -0,0 +1,161 @@
+use alloy::primitives::B256;
+use anyhow::{bail, Result};
+
+use crate::clock::Clock;
+use crate::engine::Engine;
+use crate::event::Event;
+use crate::wal::WalEntry;
+
+/// What a run of logged commands produced.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ReplayOutcome {
+    /// Events the book logged during the run, after any it started with.
+    pub events: Vec<Event>,
+    pub state_hash: B256,
+}
+
+/// Re-executes `entries` on `engine`, which must be the engine the
+/// commands first ran on, brought back with `Engine::restore` and
+/// configured the same way, and returns what the run produced.
+pub fn replay<C: Clock>(
+    mut engine: Engine<C>,
+    entries: impl IntoIterator<Item = WalEntry>,
+) -> ReplayOutcome {
+    let logged_before = engine.book().next_event_sequence();
+    engine.replay(entries);
+    ReplayOutcome {
+        events: engine
+            .book()
+            .events_since(logged_before)
+            .expect("a replay truncates no events")
+            .to_vec(),
+        state_hash: engine.state_hash(),
+    }
+}
+
+/// Replays `entries` and checks the run matches `expected`, the outcome of
+/// the original run. Fails at the first diverging event, then on a
+/// differing final state.
+pub fn verify<C: Clock>(
+    engine: Engine<C>,
+    entries: impl IntoIterator<Item = WalEntry>,
+    expected: &ReplayOutcome,
+) -> Result<()> {
+    let outcome = replay(engine, entries);
+    let diverged = outcome
+        .events
+        .iter()
+        .zip(&expected.events)
+        .position(|(replayed, original)| replayed != original);
+    if let Some(position) = diverged {
+        bail!("Replay diverged at event {position}");
+    }
+    if outcome.events.len() != expected.events.len() {
+        bail!(
+            "Replay produced {} events, expected {}",
+            outcome.events.len(),
+            expected.events.len()
+        );
+    }
+    if outcome.state_hash != expected.state_hash {
+        bail!("Replay ended in a different book state");
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::book::OrderBook;
+    use crate::clock::ManualClock;
+    use crate::engine::Command;
+    use crate::event::CancelReason;
+    use crate::market::MarketState;
+    use crate::order::OrderId;
+    use crate::test_utils::*;
+    use crate::units::Price;
+    use crate::wal::{WalConfig, WriteAheadLog};
+    use alloy::primitives::U256;
+
+    fn entries() -> Vec<WalEntry> {
+        let commands = [
+            Command::Submit(limit_ask(U256::from(3), U256::from(101))),
+            Command::Submit(stop_bid(U256::from(1), U256::from(101))),
+            Command::Submit(market_bid(U256::from(1))),
+            Command::Cancel(OrderId(1)),
+        ];
+        let entries = commands
+            .into_iter()
+            .enumerate()
+            .map(|(sequence, command)| WalEntry {
+                sequence: sequence as u64,
+                timestamp: 1,
+                command,
+            });
+        entries.collect()
+    }
+
+    #[test]
+    fn replaying_the_log_reproduces_the_live_run() {
+        let dir = std::env::temp_dir().join(format!("clobex-replay-{}", std::process::id()));
+        let _ = std::fs::remove_dir_all(&dir);
+        let wal = WriteAheadLog::open(&dir, WalConfig::default()).unwrap();
+        let book = OrderBook::from_initial_price(Price(U256::from(100)));
+        let mut engine = Engine::with_clock(book, ManualClock::at_unix_timestamp(1)).with_wal(wal);
+        let (ask, _) = engine
+            .submit(limit_ask(U256::from(3), U256::from(101)))
+            .unwrap();
+        engine
+            .submit(stop_bid(U256::from(1), U256::from(101)))
+            .unwrap();
+        engine.clock().advance(Duration::from_secs(5));
+        engine.submit(market_bid(U256::from(1))).unwrap();
+        engine.transition(MarketState::Halted).unwrap();
+        assert!(engine.submit(market_bid(U256::from(1))).is_err());
+        engine.cancel(ask).unwrap();
+        engine.transition(MarketState::Continuous).unwrap();
+        let live = ReplayOutcome {
+            events: engine.book().events().to_vec(),
+            state_hash: engine.state_hash(),
+        };
+        drop(engine);
+
+        let entries = || WriteAheadLog::read_from(&dir, 0).unwrap();
+        let fresh = || Engine::new(OrderBook::from_initial_price(Price(U256::from(100))));
+        assert_eq!(replay(fresh(), entries()), live);
+        assert!(verify(fresh(), entries(), &live).is_ok());
+        let mut truncated = entries();
+        truncated.pop();
+        assert!(verify(fresh(), truncated, &live).is_err());
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn detects_diverging_replays() {
+        let book = || OrderBook::from_initial_price(Price(U256::from(100)));
+        let original = replay(Engine::new(book()), entries());
+        assert!(verify(Engine::new(book()), entries(), &original).is_ok());
+
+        let mut tampered = original.clone();
+        let last = tampered.events.last_mut().unwrap();
+        assert_eq!(
+            *last,
+            Event::Cancelled {
+                order_id: OrderId(1),
+                reason: CancelReason::Requested,
+            }
+        );
+        *last = Event::Expired(OrderId(1));
+        let err = verify(Engine::new(book()), entries(), &tampered).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            format!("Replay diverged at event {}", original.events.len() - 1)
+        );
+
+        let configured = book().with_max_market_queue_depth(0);
+        let err = verify(Engine::new(configured), entries(), &original).unwrap_err();
+        assert_eq!(err.to_string(), "Replay diverged at event 4");
+    }
+}
@@ -0,0 +1,685 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use alloy::primitives::{keccak256, Sign, B256, I256, U256, U512};
+use anyhow::{bail, Result};
+
+use crate::book::OrderBook;
+use crate::engine::{Command, PreTradeFilter};
+use crate::event::Event;
+use crate::order::{Order, OrderId, Side};
+use crate::units::{Notional, Price, Quantity};
+
+const DAY: u64 = 24 * 60 * 60;
+
+/// `value` with `sign`, saturating at the ends of `I256`.
+fn signed(sign: Sign, value: U256) -> I256 {
+    I256::checked_from_sign_and_abs(sign, value).unwrap_or(match sign {
+        Sign::Positive => I256::MAX,
+        Sign::Negative => I256::MIN,
+    })
+}
+
+/// Value of `quantity` at `price`, saturating at the top of the range.
+fn notional(price: Price, quantity: Quantity, price_decimals: u8) -> Notional {
+    price
+        .notional(quantity, price_decimals)
+        .unwrap_or(Notional(U256::MAX))
+}
+
+/// The `part / whole` share of `value`, rounded toward zero. `part` must
+/// not exceed `whole`, so the share always fits.
+fn share(value: I256, part: U256, whole: U256) -> I256 {
+    let product: U512 = value.unsigned_abs().widening_mul(part);
+    let share = product / U512::from(whole);
+    signed(value.sign(), share.to())
+}
+
+/// An owner's position and profit for the current trading day: the
+/// position in base units, values in quote units. Sums saturate at the
+/// ends of `I256`.
+#[derive(Default)]
+struct Account {
+    /// Base quantity held; negative when short.
+    position: I256,
+    /// What the open position cost, signed like it.
+    cost: I256,
+    realized: I256,
+}
+
+impl Account {
+    /// Books a fill of `delta`, positive for a buy, worth `value`.
+    fn fill(&mut self, delta: I256, value: Notional) {
+        let quantity = delta.unsigned_abs();
+        let value = signed(delta.sign(), value.0);
+        if self.position.is_zero() || self.position.is_negative() == delta.is_negative() {
+            self.position = self.position.saturating_add(delta);
+            self.cost = self.cost.saturating_add(value);
+            return;
+        }
+        let held = self.position.unsigned_abs();
+        let closed = quantity.min(held);
+        let closed_cost = share(self.cost, closed, held);
+        let closing_value = share(value, closed, quantity);
+        // selling out of a long brings in -value; buying back a short
+        // costs value, against a cost signed like the position
+        let realized = closing_value.saturating_add(closed_cost).saturating_neg();
+        self.realized = self.realized.saturating_add(realized);
+        self.position = self.position.saturating_add(signed(delta.sign(), closed));
+        self.cost = self.cost.saturating_sub(closed_cost);
+        if quantity > held {
+            self.position = signed(delta.sign(), quantity - held);
+            self.cost = value.saturating_sub(closing_value);
+        }
+    }
+
+    /// The open position valued at `mark`.
+    fn marked(&self, mark: Price, price_decimals: u8) -> I256 {
+        let position = Quantity(self.position.unsigned_abs());
+        signed(
+            self.position.sign(),
+            notional(mark, position, price_decimals).0,
+        )
+    }
+
+    /// Realized profit plus the open position marked at `mark`.
+    fn profit(&self, mark: Price, price_decimals: u8) -> I256 {
+        self.realized
+            .saturating_add(self.marked(mark, price_decimals))
+            .saturating_sub(self.cost)
+    }
+
+    /// Starts a new day with the open position marked at `mark`, so only
+    /// moves from here count.
+    fn roll(&mut self, mark: Price, price_decimals: u8) {
+        self.realized = I256::ZERO;
+        self.cost = self.marked(mark, price_decimals);
+    }
+
+    /// Whether `order` can only shrink the position, given the `resting`
+    /// quantity the owner already has working on the order's side.
+    fn reduced_by(&self, order: &Order, resting: Quantity) -> bool {
+        let side = match self.position.is_negative() {
+            true => Side::Bid,
+            false => Side::Ask,
+        };
+        let working = (order.quantity - order.filled_quantity).saturating_add(resting);
+        !self.position.is_zero() && order.side == side && working.0 <= self.position.unsigned_abs()
+    }
+}
+
+/// Remaining quantity of `owner`'s resting orders on `side`: limit orders,
+/// stops and queued market orders. Only the owner's own orders are looked
+/// at, however many others the book holds.
+fn resting(book: &OrderBook, owner: &str, side: Side) -> Quantity {
+    let owned = book.owner_orders.get(owner).into_iter().flatten();
+    owned
+        .filter_map(|order_id| book.order(*order_id))
+        .filter(|order| order.side == side)
+        .map(|order| order.quantity - order.filled_quantity)
+        .fold(Quantity::ZERO, Quantity::saturating_add)
+}
+
+#[derive(Default)]
+struct LossState {
+    /// Events of the book's log already looked at.
+    cursor: u64,
+    day: Option<u64>,
+    /// Last traded price seen.
+    mark: Price,
+    /// Owner of every order on the book, for attributing its fills.
+    owners: HashMap<OrderId, String>,
+    accounts: HashMap<String, Account>,
+    /// Owners past the limit, until the day rolls over.
+    blocked: HashSet<String>,
+    overridden: HashSet<String>,
+}
+
+/// Pre-trade filter capping each owner's loss per trading day, realized
+/// and unrealized, marked at the last traded price. Once an owner's loss
+/// reaches the limit it may only send orders that reduce its position, and
+/// amends that don't grow an order, until the day rolls over or an
+/// operator overrides the block.
+///
+/// Positions are rebuilt from the book's trades, so the filter must see
+/// the book from its creation. Keep a handle to it by adding it to the
+/// engine inside an `Arc`.
+pub struct DailyLossLimit {
+    max_loss: Notional,
+    price_decimals: u8,
+    /// Seconds after midnight UTC each trading day starts.
+    day_start: u64,
+    state: Mutex<LossState>,
+}
+
+impl DailyLossLimit {
+    /// Limits daily losses to `max_loss`, for a market whose prices carry
+    /// `price_decimals` decimal places.
+    pub fn new(max_loss: Notional, price_decimals: u8) -> Self {
+        Self {
+            max_loss,
+            price_decimals,
+            day_start: 0,
+            state: Mutex::default(),
+        }
+    }
+
+    /// Starts trading days `offset` seconds after midnight UTC.
+    pub fn with_day_start(mut self, offset: u64) -> Self {
+        self.day_start = offset % DAY;
+        self
+    }
+
+    /// Starts a new trading day if one has begun by `now`: positions are
+    /// marked at the last traded price, losses start again from zero and
+    /// blocks and overrides are lifted. Schedule this at each day start;
+    /// the first trade of a new day also rolls it over. Fails like `check`
+    /// if the book truncated events the filter hadn't read.
+    pub fn roll_over(&self, book: &OrderBook, now: u64) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        self.sync(&mut state, book)?;
+        self.roll_to(&mut state, self.day_of(now));
+        Ok(())
+    }
+
+    /// Lets `owner` take on risk again until the day rolls over.
+    pub fn override_block(&self, owner: &str) {
+        self.state
+            .lock()
+            .unwrap()
+            .overridden
+            .insert(owner.to_string());
+    }
+
+    /// Whether `owner` has reached the limit today.
+    pub fn is_blocked(&self, owner: &str) -> bool {
+        self.state.lock().unwrap().blocked.contains(owner)
+    }
+
+    fn day_of(&self, timestamp: u64) -> u64 {
+        timestamp.saturating_sub(self.day_start) / DAY
+    }
+
+    fn roll_to(&self, state: &mut LossState, day: u64) {
+        if state.day.is_some_and(|current| current >= day) {
+            return;
+        }
+        state.day = Some(day);
+        let mark = state.mark;
+        state
+            .accounts
+            .retain(|_, account| !account.position.is_zero());
+        for account in state.accounts.values_mut() {
+            account.roll(mark, self.price_decimals);
+        }
+        state.blocked.clear();
+        state.overridden.clear();
+    }
+
+    /// Books the trades `book` logged since the last call. Fails without
+    /// booking any if the book truncated events not yet read, since the
+    /// positions would no longer be known.
+    fn sync(&self, state: &mut LossState, book: &OrderBook) -> Result<()> {
+        let events = book.events_since(state.cursor)?;
+        state.cursor = book.next_event_sequence();
+        let mut touched = Vec::new();
+        for event in events {
+            match event {
+                Event::Accepted(order) => {
+                    state.owners.insert(order.id, order.owner.clone());
+                    // orders that never rest leave no other trace
+                    touched.push(order.id);
+                }
+                Event::Traded(trade) => {
+                    self.roll_to(state, self.day_of(trade.timestamp));
+                    let bought = signed(Sign::Positive, trade.quantity.0);
+                    let sold = signed(Sign::Negative, trade.quantity.0);
+                    let (maker_delta, taker_delta) = match trade.aggressor_side {
+                        Side::Bid => (sold, bought),
+                        Side::Ask => (bought, sold),
+                    };
+                    let value = notional(trade.price, trade.quantity, self.price_decimals);
+                    for (order_id, delta) in
+                        [(trade.maker_id, maker_delta), (trade.taker_id, taker_delta)]
+                    {
+                        let Some(owner) = state.owners.get(&order_id) else {
+                            continue;
+                        };
+                        let account = state.accounts.entry(owner.clone()).or_default();
+                        account.fill(delta, value);
+                        touched.push(order_id);
+                    }
+                    state.mark = trade.price;
+                }
+                Event::Expired(order_id) | Event::Cancelled { order_id, .. } => {
+                    touched.push(*order_id);
+                }
+                _ => {}
+            }
+        }
+        for order_id in touched {
+            if book.order(order_id).is_none() {
+                state.owners.remove(&order_id);
+            }
+        }
+        Ok(())
+    }
+
+    /// Whether `owner` may add `order`, blocking it first if its loss has
+    /// reached the limit.
+    fn admits(
+        &self,
+        state: &mut LossState,
+        book: &OrderBook,
+        owner: &str,
+        order: Option<&Order>,
+    ) -> bool {
+        let Some(account) = state.accounts.get(owner) else {
+            return true;
+        };
+        let max_loss = signed(Sign::Positive, self.max_loss.0);
+        let loss = account
+            .profit(state.mark, self.price_decimals)
+            .saturating_neg();
+        if loss >= max_loss {
+            state.blocked.insert(owner.to_string());
+        }
+        !state.blocked.contains(owner)
+            || state.overridden.contains(owner)
+            || order
+                .is_some_and(|order| account.reduced_by(order, resting(book, owner, order.side)))
+    }
+}
+
+impl PreTradeFilter for DailyLossLimit {
+    fn check(&self, command: &Command, book: &OrderBook, _now: u64) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        self.sync(&mut state, book)?;
+        let admitted = match command {
+            Command::Submit(order) => self.admits(&mut state, book, &order.owner, Some(order)),
+            Command::SubmitOco(legs) => {
+                let (first, second) = &**legs;
+                self.admits(&mut state, book, &first.owner, Some(first))
+                    && self.admits(&mut state, book, &second.owner, Some(second))
+            }
+            // the child is submitted under its parent's owner
+            Command::SubmitDependent(orders) => {
+                let (parent, child) = &**orders;
+                self.admits(&mut state, book, &parent.owner, Some(parent))
+                    && self.admits(&mut state, book, &parent.owner, Some(child))
+            }
+            Command::Amend {
+                order_id,
+                new_quantity,
+                ..
+            } => match book.order(*order_id) {
+                Some(order) if *new_quantity <= order.quantity => true,
+                Some(order) => self.admits(&mut state, book, &order.owner, None),
+                None => true,
+            },
+            Command::Cancel(_)
+            | Command::CancelAll(_)
+            | Command::Expire
+            | Command::Transition(_) => true,
+        };
+        if !admitted {
+            bail!("Daily loss limit reached; only reducing orders are accepted");
+        }
+        Ok(())
+    }
+}
+
+#[derive(Default)]
+struct DuplicateState {
+    /// Events of the book's log already looked at.
+    cursor: u64,
+    /// Orders in the last command checked, with the time it ran at, until
+    /// the book's log shows whether they were accepted.
+    pending: Vec<(B256, u64)>,
+    /// When each accepted order was submitted, by content hash.
+    submitted: HashMap<B256, u64>,
+    /// The entries of `submitted`, oldest first.
+    arrivals: VecDeque<(u64, B256)>,
+}
+
+/// Pre-trade filter rejecting an order identical to one its owner had
+/// accepted within the window: same side, prices, quantity and nonce. That
+/// catches double clicks and rebroadcasts from clients that leave the
+/// nonce unset or resend it, while clients numbering every order never
+/// trip it. Orders flagged `allow_duplicate` always pass.
+///
+/// Only orders the book accepted count, read from its log, so the filter
+/// needs all of a book's events from the time it is added.
+pub struct DuplicateGuard {
+    /// In seconds.
+    window: u64,
+    state: Mutex<DuplicateState>,
+}
+
+impl DuplicateGuard {
+    /// Rejects repeats of orders accepted less than `window` ago.
+    pub fn new(window: Duration) -> Self {
+        Self {
+            window: window.as_secs(),
+            state: Mutex::default(),
+        }
+    }
+
+    /// Remembers the pending orders the book accepted since the last call.
+    /// Fails if the book truncated events not yet read.
+    fn sync(&self, state: &mut DuplicateState, book: &OrderBook) -> Result<()> {
+        let events = book.events_since(state.cursor)?;
+        state.cursor = book.next_event_sequence();
+        let pending = std::mem::take(&mut state.pending);
+        for event in events {
+            let Event::Accepted(order) = event else {
+                continue;
+            };
+            let hash = content_hash(order);
+            if let Some(&(_, now)) = pending.iter().find(|(pending, _)| *pending == hash) {
+                state.submitted.insert(hash, now);
+                state.arrivals.push_back((now, hash));
+            }
+        }
+        Ok(())
+    }
+
+    /// Forgets orders submitted a full window before `now`.
+    fn forget_before(&self, state: &mut DuplicateState, now: u64) {
+        while let Some(&(submitted, hash)) = state.arrivals.front() {
+            if submitted.saturating_add(self.window) > now {
+                return;
+            }
+            state.arrivals.pop_front();
+            if state.submitted.get(&hash) == Some(&submitted) {
+                state.submitted.remove(&hash);
+            }
+        }
+    }
+}
+
+/// Hash of what makes two orders from one owner the same order.
+fn content_hash(order: &Order) -> B256 {
+    let content = (
+        &order.owner,
+        order.side,
+        order.limit_price,
+        order.stop_price,
+        order.quantity,
+        order.nonce,
+    );
+    keccak256(serde_json::to_vec(&content).expect("orders serialize"))
+}
+
+impl PreTradeFilter for DuplicateGuard {
+    fn check(&self, command: &Command, book: &OrderBook, now: u64) -> Result<()> {
+        let mut state = self.state.lock().unwrap();
+        self.sync(&mut state, book)?;
+        self.forget_before(&mut state, now);
+        let orders = match command {
+            Command::Submit(order) => vec![order],
+            Command::SubmitOco(legs) => vec![&legs.0, &legs.1],
+            Command::SubmitDependent(orders) => vec![&orders.0],
+            Command::Cancel(_)
+            | Command::CancelAll(_)
+            | Command::Amend { .. }
+            | Command::Expire
+            | Command::Transition(_) => Vec::new(),
+        };
+        let hashes: Vec<_> = orders.iter().map(|order| content_hash(order)).collect();
+        for (order, hash) in orders.iter().zip(&hashes) {
+            if !order.allow_duplicate && state.submitted.contains_key(hash) {
+                bail!(
+                    "Order repeats one submitted in the last {} seconds",
+                    self.window
+                );
+            }
+        }
+        state.pending = hashes.into_iter().map(|hash| (hash, now)).collect();
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use super::*;
+    use crate::clock::ManualClock;
+    use crate::engine::Engine;
+    use crate::order::TimeInForce;
+    use crate::test_utils::*;
+
+    fn owned(owner: &str, mut order: Order) -> Order {
+        order.owner = owner.to_string();
+        order
+    }
+
+    #[test]
+    fn losses_past_the_limit_block_risk_until_the_day_rolls() {
+        let limit = Arc::new(DailyLossLimit::new(Notional(U256::from(50)), 0));
+        let book = OrderBook::from_initial_price(Price(U256::from(100)));
+        let mut engine =
+            Engine::with_clock(book, ManualClock::default()).with_filter(limit.clone());
+        engine
+            .submit(owned("maker", limit_ask(U256::from(10), U256::from(100))))
+            .unwrap();
+        engine
+            .submit(owned("trader", limit_bid(U256::from(10), U256::from(100))))
+            .unwrap();
+        engine
+            .submit(owned("maker", limit_ask(U256::from(1), U256::from(90))))
+            .unwrap();
+        engine
+            .submit(owned("other", limit_bid(U256::from(1), U256::from(90))))
+            .unwrap();
+
+        let err = engine
+            .submit(owned("trader", limit_bid(U256::from(1), U256::from(80))))
+            .unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Daily loss limit reached; only reducing orders are accepted"
+        );
+        assert!(limit.is_blocked("trader"));
+        assert!(!limit.is_blocked("other"));
+        assert!(engine
+            .submit(owned("trader", limit_ask(U256::from(20), U256::from(120))))
+            .is_err());
+        engine
+            .submit(owned("trader", limit_ask(U256::from(5), U256::from(120))))
+            .unwrap();
+
+        limit.override_block("trader");
+        engine
+            .submit(owned("trader", limit_bid(U256::from(1), U256::from(80))))
+            .unwrap();
+
+        limit.roll_over(engine.book(), DAY).unwrap();
+        assert!(!limit.is_blocked("trader"));
+        engine
+            .submit(owned("trader", limit_bid(U256::from(1), U256::from(80))))
+            .unwrap();
+    }
+
+    /// An engine where "trader" bought 10 at 100 and is blocked after the
+    /// price fell to 90.
+    fn blocked_trader() -> Engine<ManualClock> {
+        let limit = DailyLossLimit::new(Notional(U256::from(50)), 0);
+        let book = OrderBook::from_initial_price(Price(U256::from(100)));
+        let mut engine = Engine::with_clock(book, ManualClock::default()).with_filter(limit);
+        for (owner, order) in [
+            ("maker", limit_ask(U256::from(10), U256::from(100))),
+            ("trader", limit_bid(U256::from(10), U256::from(100))),
+            ("maker", limit_ask(U256::from(1), U256::from(90))),
+            ("other", limit_bid(U256::from(1), U256::from(90))),
+        ] {
+            engine.submit(owned(owner, order)).unwrap();
+        }
+        assert!(engine
+            .submit(owned("trader", limit_bid(U256::from(1), U256::from(80))))
+            .is_err());
+        engine
+    }
+
+    #[test]
+    fn reducing_orders_count_what_the_owner_already_has_resting() {
+        let mut engine = blocked_trader();
+        engine
+            .submit(owned("trader", limit_ask(U256::from(6), U256::from(120))))
+            .unwrap();
+        engine
+            .submit(owned("trader", stop_ask(U256::from(3), U256::from(80))))
+            .unwrap();
+        // another owner's asks don't use up the trader's position
+        engine
+            .submit(owned("other", limit_ask(U256::from(5), U256::from(130))))
+            .unwrap();
+        assert!(engine
+            .submit(owned("trader", limit_ask(U256::from(2), U256::from(120))))
+            .is_err());
+        engine
+            .submit(owned("trader", limit_ask(U256::from(1), U256::from(120))))
+            .unwrap();
+        assert!(engine
+            .submit(owned("trader", limit_ask(U256::from(1), U256::from(120))))
+            .is_err());
+    }
+
+    #[test]
+    fn queued_market_orders_count_as_resting() {
+        let mut engine = blocked_trader();
+        engine
+            .submit(owned("trader", market_ask(U256::from(9))))
+            .unwrap();
+        assert!(engine
+            .submit(owned("trader", limit_ask(U256::from(2), U256::from(120))))
+            .is_err());
+        engine
+            .submit(owned("trader", limit_ask(U256::from(1), U256::from(120))))
+            .unwrap();
+    }
+
+    #[test]
+    fn orders_that_never_rest_are_forgotten() {
+        let limit = DailyLossLimit::new(Notional(U256::from(50)), 0);
+        let mut book = OrderBook::from_initial_price(Price(U256::from(100)));
+        for time_in_force in [TimeInForce::ImmediateOrCancel, TimeInForce::FillOrKill] {
+            let mut order = limit_bid(U256::from(1), U256::from(90));
+            order.time_in_force = time_in_force;
+            book.add_order(order, 0).unwrap();
+        }
+        let (resting, _) = book
+            .add_order(limit_bid(U256::from(1), U256::from(90)), 0)
+            .unwrap();
+        limit.roll_over(&book, 0).unwrap();
+        let state = limit.state.lock().unwrap();
+        assert_eq!(state.owners.keys().collect::<Vec<_>>(), [&resting]);
+    }
+
+    #[test]
+    fn oco_legs_are_checked_against_their_own_owners() {
+        let mut engine = blocked_trader();
+        let first = owned("other", limit_bid(U256::from(1), U256::from(80)));
+        let second = owned("trader", limit_bid(U256::from(1), U256::from(70)));
+        assert!(engine.submit_oco(first.clone(), second.clone()).is_err());
+        assert!(engine.submit_oco(second, first.clone()).is_err());
+        let second = owned("other", limit_bid(U256::from(1), U256::from(70)));
+        engine.submit_oco(first, second).unwrap();
+    }
+
+    #[test]
+    fn accounts_saturate_at_the_ends_of_the_range() {
+        let mut account = Account::default();
+        let bought = signed(Sign::Positive, U256::MAX);
+        let value = notional(Price::MAX, Quantity::MAX, 0);
+        account.fill(bought, value);
+        account.fill(bought, value);
+        assert_eq!(account.position, I256::MAX);
+        assert_eq!(account.cost, I256::MAX);
+        assert_eq!(account.profit(Price::ZERO, 0), I256::MIN + I256::ONE);
+
+        // closes the capped position against its capped cost and opens a
+        // short of the one unit left over
+        account.fill(signed(Sign::Negative, U256::MAX), value);
+        assert_eq!(account.position, I256::MINUS_ONE);
+        assert_eq!(account.cost, I256::MINUS_ONE);
+        assert_eq!(account.realized, I256::ZERO);
+        account.roll(Price::MAX, 0);
+        assert_eq!(account.cost, I256::MIN);
+        assert_eq!(account.profit(Price::MAX, 0), I256::ZERO);
+
+        // fills this large go through the filter without overflowing, and
+        // a limit at the top of the range is never reached
+        let limit = Arc::new(DailyLossLimit::new(Notional(U256::MAX), u8::MAX));
+        let book = OrderBook::from_initial_price(Price(U256::from(1)));
+        let mut engine =
+            Engine::with_clock(book, ManualClock::default()).with_filter(limit.clone());
+        for _ in 0..2 {
+            engine
+                .submit(owned("maker", limit_ask(U256::MAX, U256::MAX)))
+                .unwrap();
+            engine
+                .submit(owned("trader", limit_bid(U256::MAX, U256::MAX)))
+                .unwrap();
+        }
+        engine
+            .submit(owned("maker", limit_ask(U256::from(1), U256::from(1))))
+            .unwrap();
+        engine
+            .submit(owned("other", limit_bid(U256::from(1), U256::from(1))))
+            .unwrap();
+        assert!(!limit.is_blocked("trader"));
+    }
+
+    #[test]
+    fn duplicate_guard_rejects_repeats_within_the_window() {
+        let guard = DuplicateGuard::new(Duration::from_secs(5));
+        let book = OrderBook::from_initial_price(Price(U256::from(100)));
+        let mut engine =
+            Engine::with_clock(book, ManualClock::at_unix_timestamp(1_000)).with_filter(guard);
+        let bid = || owned("trader", limit_bid(U256::from(1), U256::from(90)));
+        engine.submit(bid()).unwrap();
+
+        let err = engine.submit(bid()).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Order repeats one submitted in the last 5 seconds"
+        );
+        let mut repeat = bid();
+        repeat.allow_duplicate = true;
+        engine.submit(repeat).unwrap();
+        let mut next = bid();
+        next.nonce = U256::from(1);
+        engine.submit(next).unwrap();
+        engine
+            .submit(owned("other", limit_bid(U256::from(1), U256::from(90))))
+            .unwrap();
+
+        engine.clock().advance(Duration::from_secs(5));
+        engine.submit(bid()).unwrap();
+    }
+
+    #[test]
+    fn duplicate_guard_forgets_orders_the_book_rejected() {
+        let guard = DuplicateGuard::new(Duration::from_secs(5));
+        let book = OrderBook::from_initial_price(Price(U256::from(100)));
+        let mut engine =
+            Engine::with_clock(book, ManualClock::at_unix_timestamp(1_000)).with_filter(guard);
+        engine
+            .submit(owned("maker", limit_ask(U256::from(1), U256::from(100))))
+            .unwrap();
+        let post_only = || {
+            let mut order = owned("trader", limit_bid(U256::from(1), U256::from(100)));
+            order.post_only = true;
+            order
+        };
+        assert!(engine.submit(post_only()).is_err());
+
+        engine
+            .submit(owned("other", market_bid(U256::from(1))))
+            .unwrap();
+        engine.submit(post_only()).unwrap();
+    }
+}
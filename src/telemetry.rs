@@ -0,0 +1,218 @@
+use serde::Serialize;
+
+use crate::book::OrderBook;
+use crate::event::{CancelReason, Event};
+use crate::order::{OrderId, Side};
+use crate::units::{Price, Quantity};
+
+/// One event flattened into fixed columns, so batches load straight into
+/// a columnar store. Columns an event has no value for are `None`.
+#[derive(Clone, Debug, PartialEq, Eq, Serialize)]
+pub struct TelemetryRow {
+    /// The book's epoch, telling apart rows from before and after a
+    /// restart.
+    pub epoch: u64,
+    /// Position of the event in the book's log.
+    pub sequence: u64,
+    pub kind: &'static str,
+    /// The order the event is about; the maker for trades.
+    pub order_id: OrderId,
+    /// The taker for trades and the partner for one-cancels-other links.
+    pub counterparty_id: Option<OrderId>,
+    /// The order's side; the aggressor's side for trades.
+    pub side: Option<Side>,
+    pub price: Option<Price>,
+    pub quantity: Option<Quantity>,
+    /// Only trades carry a timestamp.
+    pub timestamp: Option<u64>,
+    pub cancel_reason: Option<CancelReason>,
+}
+
+impl TelemetryRow {
+    fn new(sequence: u64, kind: &'static str, order_id: OrderId) -> Self {
+        Self {
+            epoch: 0,
+            sequence,
+            kind,
+            order_id,
+            counterparty_id: None,
+            side: None,
+            price: None,
+            quantity: None,
+            timestamp: None,
+            cancel_reason: None,
+        }
+    }
+
+    fn from_event(sequence: u64, event: &Event) -> Self {
+        match event {
+            Event::Accepted(order) | Event::Rested { order, .. } => {
+                let kind = match event {
+                    Event::Accepted(_) => "accepted",
+                    _ => "rested",
+                };
+                Self {
+                    side: Some(order.side),
+                    price: order.limit_price.or(order.stop_price),
+                    quantity: Some(order.quantity - order.filled_quantity),
+                    ..Self::new(sequence, kind, order.id)
+                }
+            }
+            Event::Linked(first, second) => Self {
+                counterparty_id: Some(*second),
+                ..Self::new(sequence, "linked", *first)
+            },
+            Event::Traded(trade) => Self {
+                counterparty_id: Some(trade.taker_id),
+                side: Some(trade.aggressor_side),
+                price: Some(trade.price),
+                quantity: Some(trade.quantity),
+                timestamp: Some(trade.timestamp),
+                ..Self::new(sequence, "traded", trade.maker_id)
+            },
+            Event::Replenished { order_id, .. } => Self::new(sequence, "replenished", *order_id),
+            Event::Resized { order_id, quantity } => Self {
+                quantity: Some(*quantity),
+                ..Self::new(sequence, "resized", *order_id)
+            },
+            Event::Triggered(order_id) => Self::new(sequence, "triggered", *order_id),
+            Event::Expired(order_id) => Self::new(sequence, "expired", *order_id),
+            Event::Cancelled { order_id, reason } => Self {
+                cancel_reason: Some(*reason),
+                ..Self::new(sequence, "cancelled", *order_id)
+            },
+        }
+    }
+}
+
+/// Destination for sampled telemetry, such as a batch writer for the
+/// analytics store.
+pub trait TelemetrySink {
+    fn export(&mut self, rows: Vec<TelemetryRow>);
+}
+
+/// Samples a book's event log for analytics: every trade, plus one in
+/// every `sample_every` other events.
+pub struct EventSampler {
+    sample_every: u64,
+    /// Sequence number of the next event to look at.
+    cursor: u64,
+    /// Non-trade events seen so far, for picking the sample.
+    seen: u64,
+}
+
+impl EventSampler {
+    /// Samples one in every `sample_every` non-trade events; zero exports
+    /// trades only.
+    pub fn new(sample_every: u64) -> Self {
+        Self {
+            sample_every,
+            cursor: 0,
+            seen: 0,
+        }
+    }
+
+    /// Changes the sampling rate from the next event on.
+    pub fn set_sample_every(&mut self, sample_every: u64) {
+        self.sample_every = sample_every;
+    }
+
+    /// Sends the sampled events `book` logged since the last call to
+    /// `sink` as one batch. Events truncated before they were read are
+    /// skipped; the rows' sequence numbers show the gap.
+    pub fn export(&mut self, book: &OrderBook, sink: &mut impl TelemetrySink) {
+        let first = self.cursor.max(book.first_event_sequence());
+        let events = book.events_since(first).expect("held events are readable");
+        let mut rows = Vec::new();
+        for (offset, event) in events.iter().enumerate() {
+            let sampled = match event {
+                Event::Traded(_) => true,
+                _ => {
+                    self.seen += 1;
+                    self.sample_every > 0 && self.seen.is_multiple_of(self.sample_every)
+                }
+            };
+            if sampled {
+                let sequence = first + offset as u64;
+                let row = TelemetryRow::from_event(sequence, event);
+                rows.push(TelemetryRow {
+                    epoch: book.epoch(),
+                    ..row
+                });
+            }
+        }
+        self.cursor = book.next_event_sequence();
+        if !rows.is_empty() {
+            sink.export(rows);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+    use alloy::primitives::U256;
+
+    impl TelemetrySink for Vec<TelemetryRow> {
+        fn export(&mut self, rows: Vec<TelemetryRow>) {
+            self.extend(rows);
+        }
+    }
+
+    #[test]
+    fn samples_events_but_keeps_every_trade() {
+        let mut book = OrderBook::from_initial_price(Price(U256::from(100)));
+        book.add_order(limit_ask(U256::from(2), U256::from(101)), 0)
+            .unwrap();
+        book.add_order(limit_bid(U256::from(1), U256::from(101)), 0)
+            .unwrap();
+
+        let mut sampler = EventSampler::new(2);
+        let mut rows = Vec::new();
+        sampler.export(&book, &mut rows);
+        let kinds: Vec<_> = rows.iter().map(|row| row.kind).collect();
+        assert_eq!(kinds, ["rested", "traded"]);
+        assert_eq!(rows[1].sequence, 3);
+        assert_eq!(rows[1].price, Some(Price(U256::from(101))));
+
+        sampler.set_sample_every(0);
+        book.add_order(limit_bid(U256::from(1), U256::from(101)), 0)
+            .unwrap();
+        sampler.export(&book, &mut rows);
+        assert_eq!(rows.len(), 3);
+        assert_eq!(rows[2].kind, "traded");
+    }
+
+    /// Keeps each exported batch separate.
+    #[derive(Default)]
+    struct Batches(Vec<Vec<TelemetryRow>>);
+
+    impl TelemetrySink for Batches {
+        fn export(&mut self, rows: Vec<TelemetryRow>) {
+            self.0.push(rows);
+        }
+    }
+
+    #[test]
+    fn batches_skip_nothing_new_and_keep_log_sequences() {
+        let mut book = OrderBook::from_initial_price(Price(U256::from(100)));
+        let mut sampler = EventSampler::new(1);
+        let mut batches = Batches::default();
+        sampler.export(&book, &mut batches);
+        assert!(batches.0.is_empty());
+
+        book.add_order(limit_ask(U256::from(1), U256::from(101)), 0)
+            .unwrap();
+        let truncated = book.next_event_sequence();
+        book.truncate_events_before(truncated);
+        book.add_order(limit_bid(U256::from(1), U256::from(101)), 0)
+            .unwrap();
+        sampler.export(&book, &mut batches);
+        sampler.export(&book, &mut batches);
+        assert_eq!(batches.0.len(), 1);
+        let sequences: Vec<_> = batches.0[0].iter().map(|row| row.sequence).collect();
+        assert_eq!(sequences, [truncated, truncated + 1]);
+        assert_eq!(batches.0[0][1].kind, "traded");
+    }
+}
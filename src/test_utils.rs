@@ -0,0 +1,65 @@
+use alloy::primitives::U256;
+
+use crate::order::SelfTradePrevention;
+use crate::units::{Price, Quantity};
+use crate::{Order, OrderId, OrderType, Side, TimeInForce};
+
+/// Builds the order directly rather than through `Order::builder`, so tests
+/// can hand the book orders the builder would refuse.
+pub fn order(
+    side: Side,
+    quantity: U256,
+    limit_price: Option<U256>,
+    stop_price: Option<U256>,
+) -> Order {
+    let order_type = match (limit_price, stop_price) {
+        (None, None) => OrderType::Market,
+        (Some(_), None) => OrderType::Limit,
+        (None, Some(_)) => OrderType::Stop,
+        (Some(_), Some(_)) => OrderType::StopLimit,
+    };
+    Order {
+        id: OrderId::default(),
+        owner: "owner".to_string(),
+        nonce: U256::ZERO,
+        quantity: Quantity(quantity),
+        filled_quantity: Quantity::ZERO,
+        order_type,
+        limit_price: limit_price.map(Price),
+        stop_price: stop_price.map(Price),
+        expire_timestamp: 0,
+        side,
+        only_full_fill: false,
+        time_in_force: TimeInForce::GoodTilCancelled,
+        post_only: false,
+        display_quantity: Quantity::ZERO,
+        self_trade_prevention: SelfTradePrevention::Allow,
+        max_slippage_bps: None,
+        allow_duplicate: false,
+        rested_at: 0,
+    }
+}
+
+pub fn limit_ask(quantity: U256, price: U256) -> Order {
+    order(Side::Ask, quantity, Some(price), None)
+}
+
+pub fn market_bid(quantity: U256) -> Order {
+    order(Side::Bid, quantity, None, None)
+}
+
+pub fn limit_bid(quantity: U256, price: U256) -> Order {
+    order(Side::Bid, quantity, Some(price), None)
+}
+
+pub fn market_ask(quantity: U256) -> Order {
+    order(Side::Ask, quantity, None, None)
+}
+
+pub fn stop_bid(quantity: U256, stop_price: U256) -> Order {
+    order(Side::Bid, quantity, None, Some(stop_price))
+}
+
+pub fn stop_ask(quantity: U256, stop_price: U256) -> Order {
+    order(Side::Ask, quantity, None, Some(stop_price))
+}
@@ -0,0 +1,228 @@
+use std::collections::VecDeque;
+
+use alloy::primitives::U256;
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use crate::book::OrderBook;
+use crate::event::Event;
+use crate::units::{Price, Quantity};
+
+/// Rolling market summary as of the last `TickerStats::update`.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Ticker {
+    /// Price of the latest trade seen, even if it has left the window.
+    pub last_price: Option<Price>,
+    /// Traded quantity within the window, saturating at the top of the
+    /// range while the true total doesn't fit.
+    pub volume: Quantity,
+    pub high: Option<Price>,
+    pub low: Option<Price>,
+    /// Change from the first trade in the window to the latest, in basis
+    /// points, saturating at the ends of `i64`.
+    pub change_bps: Option<i64>,
+    pub best_bid: Option<Price>,
+    pub best_ask: Option<Price>,
+}
+
+/// Maintains a `Ticker` over a trailing window from a book's event log.
+/// Each trade is added and later evicted once, and the high and low are
+/// kept in monotonic queues, so updates only rescan the window to recount
+/// a volume that overflowed.
+pub struct TickerStats {
+    window: u64,
+    /// Sequence number of the next event to look at.
+    cursor: u64,
+    /// Timestamp, price and quantity of the trades in the window, oldest
+    /// first.
+    trades: VecDeque<(u64, Price, Quantity)>,
+    /// Trades that may still become the window's high: prices strictly
+    /// decrease from the front.
+    highs: VecDeque<(u64, Price)>,
+    /// Likewise for the low, with prices strictly increasing.
+    lows: VecDeque<(u64, Price)>,
+    /// Whether `ticker.volume` is capped rather than the exact sum, so
+    /// evictions must recount it.
+    saturated: bool,
+    ticker: Ticker,
+}
+
+impl TickerStats {
+    /// Length of the standard ticker window, in seconds.
+    pub const DAY: u64 = 24 * 60 * 60;
+
+    /// Summarises the trades of the last `window` seconds.
+    pub fn new(window: u64) -> Self {
+        Self {
+            window,
+            cursor: 0,
+            trades: VecDeque::new(),
+            highs: VecDeque::new(),
+            lows: VecDeque::new(),
+            saturated: false,
+            ticker: Ticker::default(),
+        }
+    }
+
+    /// Reads the book's events from `sequence` on rather than from its
+    /// creation, such as from `OrderBook::first_event_sequence` for a book
+    /// restored from a snapshot.
+    pub fn with_first_event(mut self, sequence: u64) -> Self {
+        self.cursor = sequence;
+        self
+    }
+
+    /// Adds the trades `book` logged since the last call, drops those that
+    /// fell out of the window by `now` and refreshes the best prices.
+    /// Fails without updating if the book truncated events not yet read.
+    pub fn update(&mut self, book: &OrderBook, now: u64) -> Result<Ticker> {
+        let events = book.events_since(self.cursor)?;
+        self.cursor = book.next_event_sequence();
+        for event in events {
+            if let Event::Traded(trade) = event {
+                self.record(trade.timestamp, trade.price, trade.quantity);
+            }
+        }
+        if !events.is_empty() {
+            self.ticker.best_bid = book.bids.keys().next_back().copied();
+            self.ticker.best_ask = book.asks.keys().next().copied();
+        }
+        self.evict(now);
+        Ok(self.ticker())
+    }
+
+    /// The summary as of the last update.
+    pub fn ticker(&self) -> Ticker {
+        let mut ticker = self.ticker;
+        ticker.high = self.highs.front().map(|(_, price)| *price);
+        ticker.low = self.lows.front().map(|(_, price)| *price);
+        ticker.change_bps = match (self.trades.front(), ticker.last_price) {
+            (Some(&(_, open, _)), Some(last)) => Some(change_bps(open, last)),
+            _ => None,
+        };
+        ticker
+    }
+
+    fn record(&mut self, timestamp: u64, price: Price, quantity: Quantity) {
+        self.trades.push_back((timestamp, price, quantity));
+        match self.ticker.volume.checked_add(quantity) {
+            Some(volume) => self.ticker.volume = volume,
+            None => {
+                self.ticker.volume = Quantity::MAX;
+                self.saturated = true;
+            }
+        }
+        self.ticker.last_price = Some(price);
+        while self.highs.back().is_some_and(|(_, high)| *high <= price) {
+            self.highs.pop_back();
+        }
+        self.highs.push_back((timestamp, price));
+        while self.lows.back().is_some_and(|(_, low)| *low >= price) {
+            self.lows.pop_back();
+        }
+        self.lows.push_back((timestamp, price));
+    }
+
+    fn evict(&mut self, now: u64) {
+        let expired = |timestamp: u64| timestamp.saturating_add(self.window) <= now;
+        let mut evicted = false;
+        while let Some(&(timestamp, _, quantity)) = self.trades.front() {
+            if !expired(timestamp) {
+                break;
+            }
+            self.trades.pop_front();
+            evicted = true;
+            if !self.saturated {
+                self.ticker.volume -= quantity;
+            }
+        }
+        if evicted && self.saturated {
+            // a capped sum can't be subtracted from, so count what's left
+            let mut quantities = self.trades.iter().map(|(_, _, quantity)| *quantity);
+            let volume = quantities.try_fold(Quantity::ZERO, Quantity::checked_add);
+            self.saturated = volume.is_none();
+            self.ticker.volume = volume.unwrap_or(Quantity::MAX);
+        }
+        while self
+            .highs
+            .front()
+            .is_some_and(|(timestamp, _)| expired(*timestamp))
+        {
+            self.highs.pop_front();
+        }
+        while self
+            .lows
+            .front()
+            .is_some_and(|(timestamp, _)| expired(*timestamp))
+        {
+            self.lows.pop_front();
+        }
+    }
+}
+
+fn change_bps(open: Price, last: Price) -> i64 {
+    let bps = |difference: Price| {
+        let bps = difference.0.saturating_mul(U256::from(10_000)) / open.0;
+        i64::try_from(bps).unwrap_or(i64::MAX)
+    };
+    if last >= open {
+        bps(last - open)
+    } else {
+        -bps(open - last)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_utils::*;
+
+    #[test]
+    fn ticker_rolls_trades_out_of_the_window() {
+        let mut book = OrderBook::from_initial_price(Price(U256::from(100)));
+        let mut stats = TickerStats::new(TickerStats::DAY);
+        for (price, now) in [(100, 0), (110, 10), (95, 20)] {
+            book.add_order(limit_ask(U256::from(1), U256::from(price)), now)
+                .unwrap();
+            book.add_order(limit_bid(U256::from(1), U256::from(price)), now)
+                .unwrap();
+        }
+        book.add_order(limit_bid(U256::from(1), U256::from(90)), 20)
+            .unwrap();
+
+        let ticker = stats.update(&book, 20).unwrap();
+        assert_eq!(ticker.last_price, Some(Price(U256::from(95))));
+        assert_eq!(ticker.volume, Quantity(U256::from(3)));
+        assert_eq!(ticker.high, Some(Price(U256::from(110))));
+        assert_eq!(ticker.low, Some(Price(U256::from(95))));
+        assert_eq!(ticker.change_bps, Some(-500));
+        assert_eq!(ticker.best_bid, Some(Price(U256::from(90))));
+        assert_eq!(ticker.best_ask, None);
+
+        let ticker = stats.update(&book, TickerStats::DAY + 10).unwrap();
+        assert_eq!(ticker.volume, Quantity(U256::from(1)));
+        assert_eq!(ticker.high, Some(Price(U256::from(95))));
+        assert_eq!(ticker.change_bps, Some(0));
+        let ticker = stats.update(&book, TickerStats::DAY + 20).unwrap();
+        assert_eq!(ticker.high, None);
+        assert_eq!(ticker.last_price, Some(Price(U256::from(95))));
+    }
+
+    #[test]
+    fn saturated_volume_is_recounted_on_eviction() {
+        let mut stats = TickerStats::new(10);
+        let price = Price(U256::from(100));
+        stats.record(0, price, Quantity::MAX);
+        stats.record(5, price, Quantity(U256::from(2)));
+        stats.record(6, price, Quantity(U256::from(3)));
+        stats.evict(9);
+        assert_eq!(stats.ticker().volume, Quantity::MAX);
+
+        stats.evict(10);
+        assert_eq!(stats.ticker().volume, Quantity(U256::from(5)));
+        stats.evict(15);
+        assert_eq!(stats.ticker().volume, Quantity(U256::from(3)));
+        stats.evict(16);
+        assert_eq!(stats.ticker().volume, Quantity::ZERO);
+    }
+}
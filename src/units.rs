@@ -0,0 +1,130 @@
+use std::iter::Sum;
+use std::ops::{Add, AddAssign, Sub, SubAssign};
+
+use alloy::primitives::U256;
+use serde::{Deserialize, Serialize};
+
+/// Price of one unit of quantity, in quote units scaled by the market's
+/// price decimals.
+#[derive(
+    Clone, Copy, Debug, Default, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize,
+)]
+pub struct Price(pub U256);
+
+/// Amount of the base asset.
+#[derive(
+    Clone, Copy, Debug, Default, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize,
+)]
+pub struct Quantity(pub U256);
+
+/// Value in quote units: a price times a quantity, with the price's
+/// scaling removed.
+#[derive(
+    Clone, Copy, Debug, Default, PartialEq, Eq, Hash, PartialOrd, Ord, Serialize, Deserialize,
+)]
+pub struct Notional(pub U256);
+
+/// Addition and subtraction within one unit; there is deliberately no
+/// arithmetic across units beyond `Price::notional`.
+macro_rules! same_unit_arithmetic {
+    ($($unit:ident),*) => {$(
+        impl $unit {
+            pub const ZERO: Self = Self(U256::ZERO);
+            pub const MAX: Self = Self(U256::MAX);
+
+            pub fn checked_add(self, other: Self) -> Option<Self> {
+                self.0.checked_add(other.0).map(Self)
+            }
+
+            pub fn checked_sub(self, other: Self) -> Option<Self> {
+                self.0.checked_sub(other.0).map(Self)
+            }
+
+            pub fn saturating_add(self, other: Self) -> Self {
+                Self(self.0.saturating_add(other.0))
+            }
+
+            pub fn saturating_sub(self, other: Self) -> Self {
+                Self(self.0.saturating_sub(other.0))
+            }
+        }
+
+        impl Add for $unit {
+            type Output = Self;
+
+            fn add(self, other: Self) -> Self {
+                Self(self.0 + other.0)
+            }
+        }
+
+        impl Sub for $unit {
+            type Output = Self;
+
+            fn sub(self, other: Self) -> Self {
+                Self(self.0 - other.0)
+            }
+        }
+
+        impl AddAssign for $unit {
+            fn add_assign(&mut self, other: Self) {
+                self.0 += other.0;
+            }
+        }
+
+        impl SubAssign for $unit {
+            fn sub_assign(&mut self, other: Self) {
+                self.0 -= other.0;
+            }
+        }
+
+        impl Sum for $unit {
+            fn sum<I: Iterator<Item = Self>>(iter: I) -> Self {
+                iter.fold(Self::ZERO, Add::add)
+            }
+        }
+
+        impl From<U256> for $unit {
+            fn from(value: U256) -> Self {
+                Self(value)
+            }
+        }
+    )*};
+}
+
+same_unit_arithmetic!(Price, Quantity, Notional);
+
+impl Price {
+    /// Value of `quantity` at this price, for a market whose prices carry
+    /// `price_decimals` decimal places. Rounds down; `None` on overflow.
+    pub fn notional(self, quantity: Quantity, price_decimals: u8) -> Option<Notional> {
+        let scale = U256::from(10).checked_pow(U256::from(price_decimals))?;
+        let product = self.0.checked_mul(quantity.0)?;
+        Some(Notional(product / scale))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn notional_removes_the_price_scaling() {
+        let price = Price(U256::from(12_345));
+        let quantity = Quantity(U256::from(3));
+        assert_eq!(price.notional(quantity, 2), Some(Notional(U256::from(370))));
+        assert_eq!(
+            price.notional(quantity, 0),
+            Some(Notional(U256::from(37_035)))
+        );
+        assert_eq!(Price::MAX.notional(quantity, 0), None);
+        assert_eq!(quantity - Quantity(U256::from(1)), Quantity(U256::from(2)));
+        assert_eq!(Quantity::ZERO.checked_sub(quantity), None);
+        assert_eq!(Quantity::MAX.checked_add(quantity), None);
+        assert_eq!(Quantity::MAX.saturating_add(quantity), Quantity::MAX);
+        // units serialize as the bare amount, so wire formats don't change
+        assert_eq!(
+            serde_json::to_string(&price).unwrap(),
+            serde_json::to_string(&price.0).unwrap()
+        );
+    }
+}
@@ -0,0 +1,331 @@
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, BufWriter, Write};
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Result};
+use serde::{Deserialize, Serialize};
+
+use crate::engine::Command;
+
+/// When the log syncs to disk and starts a new segment.
+#[derive(Clone, Copy, Debug)]
+pub struct WalConfig {
+    /// Appends between syncs. Entries appended since the last sync can be
+    /// lost in a crash, so 1 makes every command durable before it runs
+    /// and larger values trade that for throughput.
+    pub sync_every: usize,
+    /// Entries per segment file before the log rotates to a new one.
+    pub segment_entries: u64,
+}
+
+impl Default for WalConfig {
+    fn default() -> Self {
+        Self {
+            sync_every: 1,
+            segment_entries: 100_000,
+        }
+    }
+}
+
+/// A command as recorded in the log, with the unix timestamp the engine
+/// ran it at.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct WalEntry {
+    pub sequence: u64,
+    pub timestamp: u64,
+    pub command: Command,
+}
+
+/// Borrowing form of `WalEntry`, so appends don't clone the command.
+#[derive(Serialize)]
+struct EntryRef<'a> {
+    sequence: u64,
+    timestamp: u64,
+    command: &'a Command,
+}
+
+/// Append-only log of the commands an engine applied, stored as segment
+/// files of one JSON entry per line in a directory. Each segment is named
+/// after the sequence number of its first entry.
+pub struct WriteAheadLog {
+    dir: PathBuf,
+    config: WalConfig,
+    writer: BufWriter<File>,
+    segment_len: u64,
+    unsynced: usize,
+    next_sequence: u64,
+}
+
+impl WriteAheadLog {
+    /// Opens the log in `dir`, creating the directory if needed, and
+    /// continues numbering after the last entry already there. Appends
+    /// always go to a fresh segment, so an entry torn by a crash is left at
+    /// the end of an old one.
+    pub fn open(dir: impl Into<PathBuf>, config: WalConfig) -> Result<Self> {
+        let dir = dir.into();
+        if config.sync_every == 0 || config.segment_entries == 0 {
+            bail!("Write-ahead log batch and segment sizes must be positive");
+        }
+        fs::create_dir_all(&dir)?;
+        let next_sequence = match segments(&dir)?.last() {
+            Some((first_sequence, path)) => first_sequence + read_segment(path)?.len() as u64,
+            None => 0,
+        };
+        let writer = create_segment(&dir, next_sequence)?;
+        Ok(Self {
+            dir,
+            config,
+            writer,
+            segment_len: 0,
+            unsynced: 0,
+            next_sequence,
+        })
+    }
+
+    /// Sequence number the next appended entry gets.
+    pub fn next_sequence(&self) -> u64 {
+        self.next_sequence
+    }
+
+    /// Appends `command` and returns its sequence number, syncing if a
+    /// batch is complete.
+    pub fn append(&mut self, timestamp: u64, command: &Command) -> Result<u64> {
+        if self.segment_len == self.config.segment_entries {
+            self.sync()?;
+            self.writer = create_segment(&self.dir, self.next_sequence)?;
+            self.segment_len = 0;
+        }
+        let sequence = self.next_sequence;
+        let entry = EntryRef {
+            sequence,
+            timestamp,
+            command,
+        };
+        serde_json::to_writer(&mut self.writer, &entry)?;
+        self.writer.write_all(b"\n")?;
+        self.next_sequence += 1;
+        self.segment_len += 1;
+        self.unsynced += 1;
+        if self.unsynced >= self.config.sync_every {
+            self.sync()?;
+        }
+        Ok(sequence)
+    }
+
+    /// Flushes buffered entries and waits for them to reach the disk.
+    pub fn sync(&mut self) -> Result<()> {
+        self.writer.flush()?;
+        self.writer.get_ref().sync_data()?;
+        self.unsynced = 0;
+        Ok(())
+    }
+
+    /// Reads the entries in `dir` from `sequence` on, oldest first.
+    pub fn read_from(dir: impl AsRef<Path>, sequence: u64) -> Result<Vec<WalEntry>> {
+        let segments = segments(dir.as_ref())?;
+        // a segment can only hold entries at or after `sequence` if the
+        // next one starts after it
+        let first = segments
+            .iter()
+            .rposition(|(first_sequence, _)| *first_sequence <= sequence)
+            .unwrap_or(0);
+        let mut entries = Vec::new();
+        for (_, path) in &segments[first..] {
+            let segment = read_segment(path)?;
+            entries.extend(
+                segment
+                    .into_iter()
+                    .filter(|entry| entry.sequence >= sequence),
+            );
+        }
+        Ok(entries)
+    }
+
+    /// Deletes the segments whose entries all come before `sequence`, such
+    /// as those covered by a snapshot. The segment being written is kept.
+    pub fn truncate_before(&mut self, sequence: u64) -> Result<()> {
+        let segments = segments(&self.dir)?;
+        for pair in segments.windows(2) {
+            let ((_, path), (next_first, _)) = (&pair[0], &pair[1]);
+            if *next_first > sequence {
+                break;
+            }
+            fs::remove_file(path)?;
+        }
+        Ok(())
+    }
+}
+
+fn segment_path(dir: &Path, first_sequence: u64) -> PathBuf {
+    dir.join(format!("{first_sequence:020}.wal"))
+}
+
+fn create_segment(dir: &Path, first_sequence: u64) -> Result<BufWriter<File>> {
+    let file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(segment_path(dir, first_sequence))?;
+    Ok(BufWriter::new(file))
+}
+
+/// Segment files in `dir` with their first sequence number, in order.
+fn segments(dir: &Path) -> Result<Vec<(u64, PathBuf)>> {
+    let mut segments = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let path = entry?.path();
+        if path.extension().is_some_and(|extension| extension == "wal") {
+            let stem = path.file_stem().and_then(|stem| stem.to_str());
+            if let Some(first_sequence) = stem.and_then(|stem| stem.parse().ok()) {
+                segments.push((first_sequence, path));
+            }
+        }
+    }
+    segments.sort();
+    Ok(segments)
+}
+
+/// Reads a segment's entries. A torn last line, left by a crash mid-append,
+/// is dropped; a damaged line anywhere else fails the read, since skipping
+/// it would lose the entries after it and reuse their sequence numbers.
+fn read_segment(path: &Path) -> Result<Vec<WalEntry>> {
+    let mut entries = Vec::new();
+    let mut torn = None;
+    for (number, line) in BufReader::new(File::open(path)?).split(b'\n').enumerate() {
+        let line = line?;
+        if let Some(torn) = torn {
+            bail!(
+                "Write-ahead log segment {} is damaged at line {torn}",
+                path.display()
+            );
+        }
+        match serde_json::from_slice(&line) {
+            Ok(entry) => entries.push(entry),
+            Err(_) => torn = Some(number + 1),
+        }
+    }
+    Ok(entries)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::book::OrderBook;
+    use crate::clock::SystemClock;
+    use crate::engine::Engine;
+    use crate::test_utils::*;
+    use crate::units::Price;
+    use alloy::primitives::U256;
+
+    #[test]
+    fn replaying_the_log_recovers_the_book() {
+        let dir = std::env::temp_dir().join(format!("clobex-wal-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let config = WalConfig {
+            sync_every: 2,
+            segment_entries: 2,
+        };
+        let wal = WriteAheadLog::open(&dir, config).unwrap();
+        let mut engine = Engine::from_initial_price(Price(U256::from(100))).with_wal(wal);
+        let (ask, _) = engine
+            .submit(limit_ask(U256::from(2), U256::from(101)))
+            .unwrap();
+        engine
+            .submit(limit_ask(U256::from(1), U256::from(102)))
+            .unwrap();
+        engine.submit(market_bid(U256::from(1))).unwrap();
+        engine.cancel(ask).unwrap();
+        engine.submit(market_bid(U256::from(1))).unwrap();
+        assert!(engine.cancel(ask).is_err());
+        let expected = engine.book().snapshot();
+        drop(engine);
+
+        let entries = WriteAheadLog::read_from(&dir, 0).unwrap();
+        assert_eq!(entries.len(), 6);
+        let mut recovered = Engine::new(OrderBook::from_initial_price(Price(U256::from(100))));
+        recovered.replay(entries);
+        assert_eq!(recovered.book().snapshot(), expected);
+        assert_eq!(recovered.book().last_price_level(), Price(U256::from(102)));
+
+        let mut wal = WriteAheadLog::open(&dir, config).unwrap();
+        assert_eq!(wal.next_sequence(), 6);
+        wal.truncate_before(4).unwrap();
+        let kept = WriteAheadLog::read_from(&dir, 0).unwrap();
+        assert_eq!(kept.first().map(|entry| entry.sequence), Some(4));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn recovery_replays_from_the_snapshot_sequence() {
+        let dir = std::env::temp_dir().join(format!("clobex-checkpoint-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let wal = WriteAheadLog::open(&dir, WalConfig::default()).unwrap();
+        let mut engine = Engine::from_initial_price(Price(U256::from(100))).with_wal(wal);
+        engine
+            .submit(limit_ask(U256::from(2), U256::from(101)))
+            .unwrap();
+        engine.submit(market_bid(U256::from(1))).unwrap();
+        let snapshot = engine.snapshot();
+        assert_eq!(snapshot.wal_sequence, 2);
+        engine.submit(market_bid(U256::from(1))).unwrap();
+        let expected = engine.book().snapshot();
+        drop(engine);
+
+        let book = OrderBook::restore(snapshot.book.clone()).unwrap();
+        let mut recovered = Engine::restore(book, snapshot.clone(), SystemClock::new()).unwrap();
+        // entries the snapshot covers are skipped, not applied twice
+        recovered.replay(WriteAheadLog::read_from(&dir, 0).unwrap());
+        assert_eq!(recovered.book().snapshot(), expected);
+        assert_eq!(recovered.snapshot().wal_sequence, 3);
+
+        let stale = OrderBook::from_initial_price(Price(U256::from(100)));
+        let err = Engine::restore(stale, snapshot, SystemClock::new())
+            .err()
+            .unwrap();
+        assert_eq!(err.to_string(), "Book does not match the snapshot");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn only_a_torn_last_line_is_dropped() {
+        let dir = std::env::temp_dir().join(format!("clobex-wal-torn-{}", std::process::id()));
+        let _ = fs::remove_dir_all(&dir);
+        let mut wal = WriteAheadLog::open(&dir, WalConfig::default()).unwrap();
+        for _ in 0..2 {
+            wal.append(0, &Command::Expire).unwrap();
+        }
+        drop(wal);
+        let path = segment_path(&dir, 0);
+        let lines = fs::read_to_string(&path).unwrap();
+
+        fs::write(&path, format!("{lines}{{\"sequence\":2,\"time")).unwrap();
+        assert_eq!(WriteAheadLog::read_from(&dir, 0).unwrap().len(), 2);
+        let wal = WriteAheadLog::open(&dir, WalConfig::default()).unwrap();
+        assert_eq!(wal.next_sequence(), 2);
+        drop(wal);
+        fs::remove_file(segment_path(&dir, 2)).unwrap();
+
+        let (first, second) = lines.split_once('\n').unwrap();
+        fs::write(&path, format!("{first}\n{{\"seq\n{second}")).unwrap();
+        let err = WriteAheadLog::read_from(&dir, 0).err().unwrap();
+        assert!(err.to_string().ends_with("is damaged at line 2"));
+        assert!(WriteAheadLog::open(&dir, WalConfig::default()).is_err());
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn open_rejects_empty_batches_and_segments() {
+        let dir = std::env::temp_dir().join(format!("clobex-wal-empty-{}", std::process::id()));
+        for (sync_every, segment_entries) in [(0, 1), (1, 0)] {
+            let config = WalConfig {
+                sync_every,
+                segment_entries,
+            };
+            let err = WriteAheadLog::open(&dir, config).err().unwrap();
+            assert_eq!(
+                err.to_string(),
+                "Write-ahead log batch and segment sizes must be positive"
+            );
+        }
+        assert!(!dir.exists());
+    }
+}